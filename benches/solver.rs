@@ -0,0 +1,93 @@
+//! Benchmarks of the solver hot paths on block of fluid with 1k, 10k and 100k particles.
+//! Run by `cargo bench`, single group can be selected e.g. `cargo bench -- step`
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use fluid_renderer::Instance;
+use nikola::{real_to_single, vector, Config, Real, Solver, Vector, WCSPHSolver};
+
+const PARTICLE_RADIUS: Real = 0.01;
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+
+/// Create solver with cube of fluid resting at the bottom of the domain
+///
+/// # Arguments
+/// * `particle_num` - approximate number of particles, cube has the nearest whole side
+fn solver(particle_num: usize) -> WCSPHSolver {
+    let side = (particle_num as f64).cbrt().round() as usize;
+    let spacing = 2.0 * PARTICLE_RADIUS;
+    let size = side as Real * spacing;
+
+    let mut instances = Vec::with_capacity(side * side * side);
+    for x in 0..side {
+        for y in 0..side {
+            for z in 0..side {
+                let position = [x, y, z].map(|i| real_to_single((i as Real + 0.5) * spacing));
+                instances.push(Instance { position, color: [0.0; 3] });
+            }
+        }
+    }
+
+    let domain_end = vector(2.0 * size, 1.5 * size, size);
+    let config = Config::from_instances(Vector::ZERO, domain_end, PARTICLE_RADIUS, 1000.0, &instances);
+
+    WCSPHSolver::new(0.01, 1.0e6, 0.0, 1.0e-4, config)
+}
+
+fn initialize_particle_system(c: &mut Criterion) {
+    let mut group = c.benchmark_group("initialize_particle_system");
+    group.sample_size(10);
+    for particle_num in SIZES {
+        let mut solver = solver(particle_num);
+        group.bench_with_input(BenchmarkId::from_parameter(particle_num), &particle_num, |b, _| {
+            b.iter(|| solver.ps_mut().initialize_particle_system())
+        });
+    }
+    group.finish();
+}
+
+fn neighbor_iteration(c: &mut Criterion) {
+    let mut group = c.benchmark_group("neighbor_iteration");
+    group.sample_size(10);
+    for particle_num in SIZES {
+        let solver = solver(particle_num);
+        let ps = solver.ps();
+        group.bench_with_input(BenchmarkId::from_parameter(particle_num), &particle_num, |b, _| {
+            b.iter(|| {
+                let mut neighbors = 0;
+                for p_i in 0..ps.particle_num {
+                    ps.for_all_neighbords(p_i, |_p_i, _p_j, ret: &mut usize| *ret += 1, &mut neighbors);
+                }
+                black_box(neighbors)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn compute_densities(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compute_densities");
+    group.sample_size(10);
+    for particle_num in SIZES {
+        let mut solver = solver(particle_num);
+        group.bench_with_input(BenchmarkId::from_parameter(particle_num), &particle_num, |b, _| {
+            b.iter(|| solver.compute_densities())
+        });
+    }
+    group.finish();
+}
+
+fn step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step");
+    group.sample_size(10);
+    for particle_num in SIZES {
+        let solver = solver(particle_num);
+        // every step starts from the same state, so the measurement doesn't depend on number of iterations
+        group.bench_with_input(BenchmarkId::from_parameter(particle_num), &particle_num, |b, _| {
+            b.iter_batched_ref(|| solver.clone(), |solver| solver.step(), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, initialize_particle_system, neighbor_iteration, compute_densities, step);
+criterion_main!(benches);
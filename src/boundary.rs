@@ -0,0 +1,76 @@
+use crate::{Real, Vector};
+
+
+/// Region of the domain, which deletes every particle that crosses it
+#[derive(Debug, Clone)]
+pub enum Sink {
+    /// Axis aligned box given by its corners
+    Box { min: Vector, max: Vector },
+    /// Half space behind plane, normal points away from the fluid
+    Plane { point: Vector, normal: Vector },
+}
+
+impl Sink {
+    /// Check whether position lies inside the sink
+    ///
+    /// # Arguments
+    /// * `x` - worldspace position
+    pub fn contains(&self, x: Vector) -> bool {
+        match self {
+            Sink::Box { min, max } => x.cmpge(*min).all() && x.cmple(*max).all(),
+            Sink::Plane { point, normal } => (x - *point).dot(*normal) >= 0.0,
+        }
+    }
+}
+
+
+/// Open face of the domain, particles are not reflected by it but leave the simulation.
+/// Particles inside the buffer layer in front of the face don't compute their own forces,
+/// their pressure and velocity are extrapolated from the fluid instead, so the fluid near
+/// the outlet still has full neighbourhood
+#[derive(Debug, Clone)]
+pub struct OutflowBoundary {
+    /// Axis of the open face (0 - x, 1 - y, 2 - z)
+    pub axis: usize,
+    /// Whether the face lies on the maximal (true) or minimal (false) side of the domain
+    pub positive: bool,
+    /// Width of the buffer layer, should be at least support radius
+    pub buffer_width: Real,
+}
+
+impl OutflowBoundary {
+    /// Create new outflow boundary
+    ///
+    /// # Arguments
+    /// * `axis` - axis of the open face
+    /// * `positive` - whether the face is on the maximal side of the domain
+    /// * `buffer_width` - width of the buffer layer
+    pub fn new(axis: usize, positive: bool, buffer_width: Real) -> Self {
+        OutflowBoundary { axis, positive, buffer_width }
+    }
+
+    /// Signed distance of position from the open face, positive inside the domain
+    ///
+    /// # Arguments
+    /// * `x` - worldspace position
+    /// * `domain_start` - starting point of domain
+    /// * `domain_end` - ending point of domain
+    pub fn distance(&self, x: Vector, domain_start: Vector, domain_end: Vector) -> Real {
+        if self.positive {
+            domain_end[self.axis] - x[self.axis]
+        } else {
+            x[self.axis] - domain_start[self.axis]
+        }
+    }
+
+    /// Check whether position lies in the buffer layer
+    pub fn in_buffer(&self, x: Vector, domain_start: Vector, domain_end: Vector) -> bool {
+        let distance = self.distance(x, domain_start, domain_end);
+        distance >= 0.0 && distance < self.buffer_width
+    }
+
+    /// Check whether position already left the domain through this face
+    pub fn is_outside(&self, x: Vector, domain_start: Vector, domain_end: Vector) -> bool {
+        self.distance(x, domain_start, domain_end) < 0.0
+    }
+}
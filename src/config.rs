@@ -1,26 +1,53 @@
 use fluid_renderer::Instance;
 use glam::Vec3A;
 
+use crate::{Sink, OutflowBoundary, RigidBody, Motion, Phase, ThermalRegion, Real, Vector, from_instance};
+
 /// Configuration struct used for initialization of particle system
 pub struct Config {
     /// Starting point of domain
-    pub domain_start: Vec3A,
+    pub domain_start: Vector,
     /// Ending point of domain
-    pub domain_end: Vec3A,
+    pub domain_end: Vector,
+    /// Translation of the whole domain (sloshing tank), periodic axes are not moved
+    pub domain_motion: Option<Motion>,
+    /// Number of dimensions (2 or 3), 2D particles move in the middle xy plane of the domain
+    pub dimensions: u32,
 
     /// Radius of particle
-    pub particle_radius: f32,
+    pub particle_radius: Real,
 
     /// Amount of particles
     pub particle_num: usize,
     /// Rest density 
-    pub density_0: f32,
+    pub density_0: Real,
     /// Initial positions 
-    pub x: Vec<Vec3A>,
+    pub x: Vec<Vector>,
     /// Initial velocities
-    pub v: Vec<Vec3A>,
+    pub v: Vec<Vector>,
     /// Color of each particle
     pub color: Vec<Vec3A>,
+
+    /// Regions deleting particles
+    pub sinks: Vec<Sink>,
+    /// Open faces of the domain
+    pub outflows: Vec<OutflowBoundary>,
+    /// Whether the domain wraps around along x, y and z axis
+    pub periodic: [bool; 3],
+    /// Rigid bodies coupled with fluid
+    pub rigid_bodies: Vec<RigidBody>,
+
+    /// Fluid phases, when empty all particles share `density_0` and solver's viscosity
+    pub phases: Vec<Phase>,
+    /// Phase of each particle
+    pub phase: Vec<usize>,
+    /// Tension between particles of different phases
+    pub interface_tension: Real,
+
+    /// Initial temperature of each particle (°C)
+    pub temperature: Vec<Real>,
+    /// Heated and cooled regions
+    pub thermal_regions: Vec<ThermalRegion>,
 }
 
 impl Config {
@@ -36,21 +63,32 @@ impl Config {
     /// # Returns
     /// new configuration struct
     pub fn from_instances(
-        domain_start: Vec3A, 
-        domain_end: Vec3A,
-        particle_radius: f32,
-        density_0: f32,
+        domain_start: Vector, 
+        domain_end: Vector,
+        particle_radius: Real,
+        density_0: Real,
         instances: &Vec<Instance>,
     ) -> Self {
         Config { 
             domain_start, 
             domain_end, 
+            domain_motion: None,
+            dimensions: 3,
             particle_radius, 
             particle_num: instances.len(), 
             density_0, 
-            x: instances.iter().map(|instance| instance.position.into()).collect(), 
-            v: vec![Vec3A::ZERO; instances.len()], 
-            color: instances.iter().map(|instance| instance.color.into()).collect()
+            x: instances.iter().map(|instance| from_instance(instance.position)).collect(), 
+            v: vec![Vector::ZERO; instances.len()], 
+            color: instances.iter().map(|instance| instance.color.into()).collect(),
+            sinks: Vec::new(),
+            outflows: Vec::new(),
+            periodic: [false; 3],
+            rigid_bodies: Vec::new(),
+            phases: Vec::new(),
+            phase: vec![0; instances.len()],
+            interface_tension: 0.0,
+            temperature: vec![20.0; instances.len()],
+            thermal_regions: Vec::new(),
         }
     }
 }
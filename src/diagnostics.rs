@@ -0,0 +1,153 @@
+use std::fs::write;
+
+use crate::{Solver, Real, Vector};
+
+
+/// Health statistics of the simulation computed after a step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Diagnostics {
+    /// Simulation time (s)
+    pub time: Real,
+    pub kinetic_energy: Real,
+    /// Potential energy in the gravity field, zero at the origin
+    pub potential_energy: Real,
+    /// Total linear momentum of particles
+    pub momentum: Vector,
+    /// Average relative deviation of density from rest density
+    pub average_density_error: Real,
+    /// Maximal relative deviation of density from rest density
+    pub max_density_error: Real,
+    pub max_velocity: Real,
+    /// Courant number, distance traveled by the fastest particle in one step relative to particle diameter
+    pub cfl: Real,
+    pub min_neighbors: usize,
+    pub average_neighbors: Real,
+    pub max_neighbors: usize,
+}
+
+impl Diagnostics {
+    /// Compute statistics of the current state of the solver, rest density of each particle is
+    /// given by ratio of its mass and volume. Neighbours are counted at the positions after the
+    /// step, particles moved by the step are looked up in separately sorted cells
+    ///
+    /// # Arguments
+    /// * `solver` - examined solver
+    /// * `delta_time` - length of time step of the solver
+    /// * `gravity` - gravitational acceleration
+    pub fn compute<S: Solver + ?Sized>(solver: &S, delta_time: Real, gravity: Vector) -> Self {
+        let ps = solver.ps();
+        let cells = ps.grid_cells();
+        let mut diagnostics = Diagnostics {
+            time: ps.time,
+            min_neighbors: if ps.particle_num > 0 { usize::MAX } else { 0 },
+            ..Default::default()
+        };
+
+        for p_i in 0..ps.particle_num {
+            let m = ps.m[p_i];
+            let speed = ps.v[p_i].length();
+            diagnostics.kinetic_energy += 0.5 * m * speed * speed;
+            diagnostics.potential_energy -= m * gravity.dot(ps.x[p_i]);
+            diagnostics.momentum += m * ps.v[p_i];
+            diagnostics.max_velocity = diagnostics.max_velocity.max(speed);
+
+            let density_0 = m / ps.m_v[p_i];
+            let density_error = ((ps.density[p_i] - density_0) / density_0).abs();
+            diagnostics.average_density_error += density_error;
+            diagnostics.max_density_error = diagnostics.max_density_error.max(density_error);
+
+            let mut neighbors = 0;
+            ps.for_all_particles_near_in(&cells, ps.x[p_i], |p_j, _r| neighbors += usize::from(p_j != p_i));
+            diagnostics.min_neighbors = diagnostics.min_neighbors.min(neighbors);
+            diagnostics.max_neighbors = diagnostics.max_neighbors.max(neighbors);
+            diagnostics.average_neighbors += neighbors as Real;
+        }
+
+        if ps.particle_num > 0 {
+            diagnostics.average_density_error /= ps.particle_num as Real;
+            diagnostics.average_neighbors /= ps.particle_num as Real;
+        }
+        diagnostics.cfl = diagnostics.max_velocity * delta_time / ps.particle_diameter;
+
+        diagnostics
+    }
+
+    /// Get sum of kinetic and potential energy
+    pub fn total_energy(&self) -> Real {
+        self.kinetic_energy + self.potential_energy
+    }
+}
+
+
+/// Time series of diagnostics, one record per solver step
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticsLog {
+    pub records: Vec<Diagnostics>,
+}
+
+impl DiagnosticsLog {
+    /// Create empty log
+    pub fn new() -> Self {
+        DiagnosticsLog { records: Vec::new() }
+    }
+
+    /// Append record to the log
+    ///
+    /// # Arguments
+    /// * `diagnostics` - statistics after step
+    pub fn push(&mut self, diagnostics: Diagnostics) {
+        self.records.push(diagnostics);
+    }
+
+    /// Get the latest record
+    pub fn last(&self) -> Option<&Diagnostics> {
+        self.records.last()
+    }
+
+    /// Get relative change of total energy since the first record, used to compare integrators
+    ///
+    /// # Arguments
+    /// * `diagnostics` - compared record
+    pub fn energy_drift(&self, diagnostics: &Diagnostics) -> Real {
+        match self.records.first() {
+            Some(first) if first.total_energy().abs() > Real::EPSILON => {
+                (diagnostics.total_energy() - first.total_energy()) / first.total_energy().abs()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Write log as CSV file with header
+    ///
+    /// # Arguments
+    /// * `path` - path to the target file
+    pub fn save(&self, path: String) -> Result<(), std::io::Error> {
+        let mut csv = String::from(
+            "time,kinetic_energy,potential_energy,total_energy,energy_drift,momentum_x,momentum_y,momentum_z,\
+            average_density_error,max_density_error,max_velocity,cfl,min_neighbors,average_neighbors,max_neighbors\n"
+        );
+
+        for record in self.records.iter() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                record.time,
+                record.kinetic_energy,
+                record.potential_energy,
+                record.total_energy(),
+                self.energy_drift(record),
+                record.momentum.x,
+                record.momentum.y,
+                record.momentum.z,
+                record.average_density_error,
+                record.max_density_error,
+                record.max_velocity,
+                record.cfl,
+                record.min_neighbors,
+                record.average_neighbors,
+                record.max_neighbors,
+            ));
+        }
+
+        write(path, csv)
+    }
+}
@@ -0,0 +1,359 @@
+use std::fs::{write, read};
+
+use glam::Vec3A;
+
+use crate::{Solver, Real, Vector, consts, to_single, from_single};
+
+
+/// Kind of diffuse particle, given by the number of fluid neighbors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffuseKind {
+    /// Few fluid neighbors, moves ballistically
+    Spray,
+    /// Lies on the surface, carried by the fluid until it dissolves
+    Foam,
+    /// Surrounded by fluid, rises by buoyancy
+    Bubble,
+}
+
+
+/// Secondary particle used for rendering of whitewater
+#[derive(Debug, Clone, Copy)]
+pub struct DiffuseParticle {
+    pub x: Vector,
+    pub v: Vector,
+    /// Remaining lifetime (s), decreases only for foam
+    pub lifetime: Real,
+    pub kind: DiffuseKind,
+}
+
+
+/// Parameters of the diffuse particle generator (Ihmsen et al. 2012)
+#[derive(Debug, Clone, Copy)]
+pub struct DiffuseSettings {
+    /// Clamping interval of trapped air potential
+    pub trapped_air: (Real, Real),
+    /// Clamping interval of wave crest potential
+    pub wave_crest: (Real, Real),
+    /// Clamping interval of kinetic energy potential
+    pub kinetic_energy: (Real, Real),
+    /// Maximal number of particles generated by trapped air per fluid particle and second
+    pub trapped_air_rate: Real,
+    /// Maximal number of particles generated by wave crests per fluid particle and second
+    pub wave_crest_rate: Real,
+    /// Initial lifetime of particle (s)
+    pub lifetime: Real,
+    /// Particles with fewer fluid neighbors are spray
+    pub spray_neighbors: usize,
+    /// Particles with more fluid neighbors are bubbles
+    pub bubble_neighbors: usize,
+    /// Buoyancy of bubbles relative to gravity
+    pub buoyancy: Real,
+    /// Rate at which bubbles take velocity of the fluid
+    pub drag: Real,
+    /// Upper limit of number of diffuse particles
+    pub max_particles: usize,
+}
+
+impl Default for DiffuseSettings {
+    fn default() -> Self {
+        DiffuseSettings {
+            trapped_air: (5.0, 20.0),
+            wave_crest: (2.0, 8.0),
+            kinetic_energy: (5.0, 50.0),
+            trapped_air_rate: 40.0,
+            wave_crest_rate: 40.0,
+            lifetime: 2.0,
+            spray_neighbors: 6,
+            bubble_neighbors: 20,
+            buoyancy: 2.0,
+            drag: 0.8,
+            max_particles: 200_000,
+        }
+    }
+}
+
+
+/// Generates diffuse particles from fluid particles and advects them
+pub struct DiffuseGenerator {
+    pub settings: DiffuseSettings,
+    pub particles: Vec<DiffuseParticle>,
+    seed: u32,
+}
+
+impl DiffuseGenerator {
+    /// Create generator without particles
+    ///
+    /// # Arguments
+    /// * `settings` - parameters of generator
+    pub fn new(settings: DiffuseSettings) -> Self {
+        DiffuseGenerator { settings, particles: Vec::new(), seed: 0x9e37_79b9 }
+    }
+
+    /// Advect existing diffuse particles and generate new ones from current state of the fluid
+    ///
+    /// # Arguments
+    /// * `solver` - solver with sorted particle system
+    /// * `delta_time` - time elapsed since the last update (s)
+    /// * `gravity` - gravitational acceleration
+    pub fn update<S: Solver>(&mut self, solver: &S, delta_time: Real, gravity: Vector) {
+        self.advect(solver, delta_time, gravity);
+        self.generate(solver, delta_time);
+    }
+
+    /// Compute potentials of fluid particles and emit new diffuse particles
+    ///
+    /// # Arguments
+    /// * `solver` - solver with sorted particle system
+    /// * `delta_time` - time elapsed since the last update (s)
+    fn generate<S: Solver>(&mut self, solver: &S, delta_time: Real) {
+        let ps = solver.ps();
+        let h = solver.support_radius();
+
+        let normals = (0..ps.particle_num)
+            .map(|p_i| {
+                let mut normal = Vector::ZERO;
+                ps.for_all_neighbords(p_i, |p_i, p_j, ret| {
+                    let volume = ps.m[p_j] / ps.density[p_j];
+                    *ret -= volume * solver.cubic_kernel_derivative(ps.relative_position(p_i, p_j));
+                }, &mut normal);
+                normal
+            })
+            .collect::<Vec<Vector>>();
+
+        for p_i in 0..ps.particle_num {
+            let v_i = ps.v[p_i];
+            let n_i = normals[p_i].normalize_or_zero();
+
+            let mut potentials = (0.0, 0.0);
+            ps.for_all_neighbords(p_i, |p_i, p_j, ret: &mut (Real, Real)| {
+                let x_ij = ps.relative_position(p_i, p_j);
+                let v_ij = ps.v[p_i] - ps.v[p_j];
+                let weight = 1.0 - x_ij.length() / h;
+
+                ret.0 += v_ij.length() * (1.0 - v_ij.normalize_or_zero().dot(x_ij.normalize_or_zero())) * weight;
+                if (-x_ij).normalize_or_zero().dot(n_i) < 0.0 {
+                    ret.1 += (1.0 - n_i.dot(normals[p_j].normalize_or_zero())) * weight;
+                }
+            }, &mut potentials);
+
+            let (trapped_air, mut wave_crest) = potentials;
+            if v_i.normalize_or_zero().dot(n_i) < 0.6 {
+                wave_crest = 0.0;
+            }
+            let kinetic_energy = 0.5 * ps.m[p_i] * v_i.length_squared();
+
+            let rate = (
+                self.settings.trapped_air_rate * clamp_potential(trapped_air, self.settings.trapped_air)
+                + self.settings.wave_crest_rate * clamp_potential(wave_crest, self.settings.wave_crest)
+            ) * clamp_potential(kinetic_energy, self.settings.kinetic_energy);
+
+            let expected = rate * delta_time;
+            let count = expected.floor() as usize + (self.random() < expected.fract()) as usize;
+            for _ in 0..count {
+                if self.particles.len() >= self.settings.max_particles {
+                    return;
+                }
+                self.emit(ps.x[p_i], v_i, ps.particle_radius, delta_time);
+            }
+        }
+    }
+
+    /// Emit particle in cylinder spanned by velocity of the fluid particle
+    ///
+    /// # Arguments
+    /// * `x` - position of fluid particle
+    /// * `v` - velocity of fluid particle
+    /// * `radius` - radius of the cylinder
+    /// * `delta_time` - time elapsed since the last update (s)
+    fn emit(&mut self, x: Vector, v: Vector, radius: Real, delta_time: Real) {
+        let direction = v.normalize_or_zero();
+        let e_1 = direction.any_orthonormal_vector();
+        let e_2 = direction.cross(e_1);
+
+        let r = radius * self.random().sqrt();
+        let theta = 2.0 * consts::PI * self.random();
+        let height = self.random() * delta_time * v.length();
+        let radial = r * (theta.cos() * e_1 + theta.sin() * e_2);
+
+        self.particles.push(DiffuseParticle {
+            x: x + radial + height * direction,
+            v: v + radial,
+            lifetime: self.settings.lifetime,
+            kind: DiffuseKind::Foam,
+        });
+    }
+
+    /// Classify diffuse particles and move them by rules of their kind, dissolved particles and
+    /// particles outside of the domain are removed
+    ///
+    /// # Arguments
+    /// * `solver` - solver with sorted particle system
+    /// * `delta_time` - time elapsed since the last update (s)
+    /// * `gravity` - gravitational acceleration
+    fn advect<S: Solver>(&mut self, solver: &S, delta_time: Real, gravity: Vector) {
+        let ps = solver.ps();
+        let settings = self.settings;
+
+        for particle in self.particles.iter_mut() {
+            let mut neighbors = 0;
+            let mut weight = 0.0;
+            let mut fluid_v = Vector::ZERO;
+            ps.for_all_particles_near(particle.x, |p_j, r| {
+                let w = solver.cubic_kernel(r.length());
+                neighbors += 1;
+                weight += w;
+                fluid_v += w * ps.v[p_j];
+            });
+            if weight > 0.0 {
+                fluid_v /= weight;
+            }
+
+            particle.kind = if neighbors < settings.spray_neighbors {
+                DiffuseKind::Spray
+            } else if neighbors > settings.bubble_neighbors {
+                DiffuseKind::Bubble
+            } else {
+                DiffuseKind::Foam
+            };
+
+            match particle.kind {
+                DiffuseKind::Spray => {
+                    particle.v += delta_time * gravity;
+                }
+                DiffuseKind::Foam => {
+                    particle.v = fluid_v;
+                    particle.lifetime -= delta_time;
+                }
+                DiffuseKind::Bubble => {
+                    particle.v += -delta_time * settings.buoyancy * gravity + settings.drag * (fluid_v - particle.v);
+                }
+            }
+            particle.x += delta_time * particle.v;
+        }
+
+        self.particles.retain(|particle| {
+            particle.lifetime > 0.0
+                && particle.x.cmpge(ps.domain_start).all()
+                && particle.x.cmple(ps.domain_end).all()
+        });
+    }
+
+    /// Get uniformly distributed number from interval [0, 1)
+    fn random(&mut self) -> Real {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+
+        (self.seed >> 8) as Real / (1 << 24) as Real
+    }
+}
+
+
+/// Map potential to interval [0, 1]
+///
+/// # Arguments
+/// * `value` - potential
+/// * `interval` - minimal and maximal potential
+fn clamp_potential(value: Real, interval: (Real, Real)) -> Real {
+    let (min, max) = interval;
+    (value.min(max) - value.min(min)) / (max - min)
+}
+
+
+/// Diffuse particles of each frame, stored in separate file next to the simulation
+#[derive(Debug)]
+pub struct DiffuseRecording {
+    pub fps: u32,
+    pub frames: Vec<Vec<DiffuseParticle>>,
+}
+
+impl DiffuseRecording {
+    /// Create recording without frames
+    ///
+    /// # Arguments
+    /// * `fps` - frames per second of the simulation
+    pub fn new(fps: u32) -> Self {
+        DiffuseRecording { fps, frames: Vec::new() }
+    }
+
+    /// Store current diffuse particles as frame
+    ///
+    /// # Arguments
+    /// * `frame` - index of the frame
+    /// * `particles` - current diffuse particles
+    pub fn record(&mut self, frame: usize, particles: &[DiffuseParticle]) {
+        if self.frames.len() <= frame {
+            self.frames.resize(frame + 1, Vec::new());
+        }
+        self.frames[frame] = particles.to_vec();
+    }
+
+    /// Write recording into file at path, each frame is stored as particle count followed by
+    /// single precision position and kind of each particle
+    ///
+    /// # Arguments
+    /// * `path` - path to the target file
+    pub fn save(&self, path: String) -> Result<(), std::io::Error> {
+        let mut bytes = self.fps.to_ne_bytes().to_vec();
+        bytes.extend((self.frames.len() as u32).to_ne_bytes());
+
+        for frame in self.frames.iter() {
+            bytes.extend((frame.len() as u32).to_ne_bytes());
+            for particle in frame.iter() {
+                bytes.extend_from_slice(bytemuck::cast_slice(&to_single(particle.x).to_array()));
+                bytes.extend((particle.kind as u32).to_ne_bytes());
+            }
+        }
+
+        write(path, bytes)
+    }
+
+    /// Read recording from file at path, velocities and lifetimes are not stored
+    ///
+    /// # Arguments
+    /// * `path` - path to the source file
+    pub fn from_file(path: String) -> Result<Self, std::io::Error> {
+        let bytes = read(path)?;
+        let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "Truncated diffuse recording");
+        let read_u32 = |offset: usize| {
+            bytes.get(offset..offset + 4).map(|slice| u32::from_ne_bytes(slice.try_into().unwrap())).ok_or_else(invalid)
+        };
+
+        let fps = read_u32(0)?;
+        let frame_num = read_u32(4)? as usize;
+        let mut offset = 8;
+        // counts are bounded by the remaining bytes, so corrupted counts do not allocate huge vectors
+        if frame_num > (bytes.len() - offset) / 4 {
+            return Err(invalid());
+        }
+        let mut frames = Vec::with_capacity(frame_num);
+
+        for _frame in 0..frame_num {
+            let particle_num = read_u32(offset)? as usize;
+            offset += 4;
+            if particle_num > (bytes.len() - offset) / 16 {
+                return Err(invalid());
+            }
+
+            let mut particles = Vec::with_capacity(particle_num);
+            for _particle in 0..particle_num {
+                let x = from_single(Vec3A::new(
+                    f32::from_bits(read_u32(offset)?),
+                    f32::from_bits(read_u32(offset + 4)?),
+                    f32::from_bits(read_u32(offset + 8)?),
+                ));
+                let kind = match read_u32(offset + 12)? {
+                    0 => DiffuseKind::Spray,
+                    1 => DiffuseKind::Foam,
+                    _ => DiffuseKind::Bubble,
+                };
+                particles.push(DiffuseParticle { x, v: Vector::ZERO, lifetime: 0.0, kind });
+                offset += 16;
+            }
+            frames.push(particles);
+        }
+
+        Ok(DiffuseRecording { fps, frames })
+    }
+}
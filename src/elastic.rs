@@ -0,0 +1,461 @@
+use crate::{Solver, ParticleSystem, Config, Real, Vector, Matrix, Rotation, vector};
+
+
+/// Material of elastic or plastic solid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ElasticMaterial {
+    /// Young's modulus (Pa)
+    pub youngs_modulus: Real,
+    /// Poisson ratio, should be lower than 0.5
+    pub poisson_ratio: Real,
+    /// Elastic strain, above which the material deforms plastically, infinity for jelly
+    pub yield_strain: Real,
+    /// Fraction of the strain above yield, which is turned into plastic strain each step
+    pub creep: Real,
+    /// Upper limit of plastic strain
+    pub max_plastic_strain: Real,
+    /// Rate of damping of relative motion of the neighbors (1/s)
+    pub damping: Real,
+}
+
+impl ElasticMaterial {
+    /// Create purely elastic material
+    ///
+    /// # Arguments
+    /// * `youngs_modulus` - Young's modulus
+    /// * `poisson_ratio` - Poisson ratio
+    pub fn new(youngs_modulus: Real, poisson_ratio: Real) -> Self {
+        ElasticMaterial {
+            youngs_modulus,
+            poisson_ratio,
+            yield_strain: Real::INFINITY,
+            creep: 0.0,
+            max_plastic_strain: 0.0,
+            damping: 1.0,
+        }
+    }
+
+    /// Create elastoplastic material, e.g. clay
+    ///
+    /// # Arguments
+    /// * `youngs_modulus` - Young's modulus
+    /// * `poisson_ratio` - Poisson ratio
+    /// * `yield_strain` - strain at which the material starts to deform plastically
+    /// * `max_plastic_strain` - upper limit of plastic strain
+    pub fn plastic(youngs_modulus: Real, poisson_ratio: Real, yield_strain: Real, max_plastic_strain: Real) -> Self {
+        ElasticMaterial {
+            yield_strain,
+            creep: 1.0,
+            max_plastic_strain,
+            ..ElasticMaterial::new(youngs_modulus, poisson_ratio)
+        }
+    }
+
+    /// Get Lamé parameters (mu, lambda)
+    pub fn lame(&self) -> (Real, Real) {
+        let mu = self.youngs_modulus / (2.0 * (1.0 + self.poisson_ratio));
+        let lambda = self.youngs_modulus * self.poisson_ratio / ((1.0 + self.poisson_ratio) * (1.0 - 2.0 * self.poisson_ratio));
+
+        (mu, lambda)
+    }
+}
+
+
+/// Corotated SPH elasticity (Becker et al. 2009) with plasticity (O'Brien et al. 2002).
+/// Neighborhoods and corrected kernel gradients are taken from the rest configuration `x_0`,
+/// state of particles is stored by their original id, so it survives sorting
+#[derive(Debug, Clone, Default)]
+pub struct Elasticity {
+    /// Material of each phase, phases without material are not elastic
+    pub materials: Vec<Option<ElasticMaterial>>,
+    rest_neighbors: Vec<Vec<usize>>,
+    rest_gradients: Vec<Vec<Vector>>,
+    rest_reverse: Vec<Vec<usize>>,
+    rotation: Vec<Rotation>,
+    plastic_strain: Vec<Matrix>,
+    /// Elastic stress of each particle (by original id)
+    pub stress: Vec<Matrix>,
+}
+
+impl Elasticity {
+    /// Iterations of rotation extraction, rotations of the last step are used as initial guess
+    const ROTATION_ITERATIONS: usize = 5;
+
+    /// Build rest neighborhoods of elastic particles, particles of different phases are not
+    /// connected, so separate objects don't stick together. Particles have to be in their rest
+    /// configuration
+    ///
+    /// # Arguments
+    /// * `solver` - solver with sorted particle system in rest configuration
+    /// * `materials` - material of each phase
+    pub fn new<S: Solver>(solver: &S, materials: Vec<Option<ElasticMaterial>>) -> Self {
+        let ps = solver.ps();
+        let original_num = ps.ids.iter().max().map_or(0, |id| id + 1);
+
+        let mut rest_neighbors = vec![Vec::new(); original_num];
+        let mut rest_gradients = vec![Vec::new(); original_num];
+
+        for p_i in 0..ps.particle_num {
+            if material(&materials, ps, p_i).is_none() {
+                continue;
+            }
+
+            let mut neighbors = Vec::new();
+            ps.for_all_neighbords(p_i, |p_i, p_j, ret: &mut Vec<(usize, Vector)>| {
+                if ps.phase[p_i] == ps.phase[p_j] {
+                    ret.push((p_j, solver.cubic_kernel_derivative(ps.x_0[p_i] - ps.x_0[p_j])));
+                }
+            }, &mut neighbors);
+
+            // correction matrix, restores exact gradient of linear fields in rest configuration
+            let mut moment = neighbors.iter().fold(Matrix::ZERO, |moment, (p_j, gradient)| {
+                moment + ps.m_v[*p_j] * outer(ps.x_0[*p_j] - ps.x_0[p_i], *gradient)
+            });
+            // 2D neighborhood has no extent along z, which is left uncorrected
+            if ps.dimensions == 2 {
+                moment.z_axis.z = 1.0;
+            }
+            let correction = if moment.determinant().abs() > Real::EPSILON {
+                moment.inverse().transpose()
+            } else {
+                Matrix::IDENTITY
+            };
+
+            let id = ps.ids[p_i];
+            rest_neighbors[id] = neighbors.iter().map(|(p_j, _)| ps.ids[*p_j]).collect();
+            rest_gradients[id] = neighbors.iter().map(|(_, gradient)| correction * *gradient).collect();
+        }
+
+        let rest_reverse = rest_neighbors
+            .iter()
+            .enumerate()
+            .map(|(id, neighbors)| {
+                neighbors
+                    .iter()
+                    .map(|id_j| rest_neighbors[*id_j].iter().position(|id_k| *id_k == id).unwrap_or(usize::MAX))
+                    .collect()
+            })
+            .collect();
+
+        Elasticity {
+            materials,
+            rest_neighbors,
+            rest_gradients,
+            rest_reverse,
+            rotation: vec![Rotation::IDENTITY; original_num],
+            plastic_strain: vec![Matrix::ZERO; original_num],
+            stress: vec![Matrix::ZERO; original_num],
+        }
+    }
+
+    /// Check whether particle is elastic
+    ///
+    /// # Arguments
+    /// * `ps` - particle system
+    /// * `p_i` - particle id
+    pub fn is_elastic(&self, ps: &ParticleSystem, p_i: usize) -> bool {
+        material(&self.materials, ps, p_i).is_some()
+    }
+
+    /// Update rotations, plastic strains and stresses, then compute elastic acceleration of each
+    /// particle
+    ///
+    /// # Arguments
+    /// * `ps` - particle system
+    /// * `delta_time` - length of time step (s)
+    ///
+    /// # Returns
+    /// acceleration of each particle, zero for particles, which aren't elastic
+    pub fn compute_accelerations(&mut self, ps: &ParticleSystem, delta_time: Real) -> Vec<Vector> {
+        let mut index_of = vec![usize::MAX; self.rest_neighbors.len()];
+        for (p_i, id) in ps.ids.iter().enumerate() {
+            index_of[*id] = p_i;
+        }
+
+        let mut first_piola = vec![Matrix::ZERO; ps.particle_num];
+        for p_i in 0..ps.particle_num {
+            let material = match material(&self.materials, ps, p_i) {
+                Some(material) => material,
+                None => continue,
+            };
+            let id = ps.ids[p_i];
+
+            // deformation gradient and its rotation
+            let mut deformation = Matrix::ZERO;
+            for (id_j, gradient) in self.rest_neighbors[id].iter().zip(self.rest_gradients[id].iter()) {
+                let p_j = index_of[*id_j];
+                if p_j != usize::MAX {
+                    deformation += ps.m_v[p_j] * outer(ps.relative_position(p_j, p_i), *gradient);
+                }
+            }
+            if self.rest_neighbors[id].is_empty() {
+                deformation = Matrix::IDENTITY;
+            }
+            self.rotation[id] = extract_rotation(deformation, self.rotation[id], Self::ROTATION_ITERATIONS);
+            let rotation = Matrix::from_quat(self.rotation[id]);
+
+            // corotated linear strain
+            let mut displacement_gradient = Matrix::ZERO;
+            for (id_j, gradient) in self.rest_neighbors[id].iter().zip(self.rest_gradients[id].iter()) {
+                let p_j = index_of[*id_j];
+                if p_j != usize::MAX {
+                    let displacement = rotation.transpose() * ps.relative_position(p_j, p_i) - (ps.x_0[p_j] - ps.x_0[p_i]);
+                    displacement_gradient += ps.m_v[p_j] * outer(displacement, *gradient);
+                }
+            }
+            let strain = 0.5 * (displacement_gradient + displacement_gradient.transpose());
+
+            // plastic flow
+            let mut elastic_strain = strain - self.plastic_strain[id];
+            let norm = frobenius_norm(elastic_strain);
+            if norm > material.yield_strain {
+                let mut plastic_strain = self.plastic_strain[id] + material.creep * (norm - material.yield_strain) / norm * elastic_strain;
+                let plastic_norm = frobenius_norm(plastic_strain);
+                if plastic_norm > material.max_plastic_strain {
+                    plastic_strain = material.max_plastic_strain / plastic_norm * plastic_strain;
+                }
+                self.plastic_strain[id] = plastic_strain;
+                elastic_strain = strain - plastic_strain;
+            }
+
+            let (mu, lambda) = material.lame();
+            let trace = elastic_strain.x_axis.x + elastic_strain.y_axis.y + elastic_strain.z_axis.z;
+            self.stress[id] = 2.0 * mu * elastic_strain + lambda * trace * Matrix::IDENTITY;
+            first_piola[p_i] = rotation * self.stress[id];
+        }
+
+        let mut accelerations = vec![Vector::ZERO; ps.particle_num];
+        for p_i in 0..ps.particle_num {
+            let material = match material(&self.materials, ps, p_i) {
+                Some(material) => material,
+                None => continue,
+            };
+            let id = ps.ids[p_i];
+
+            let mut force = Vector::ZERO;
+            let mut relative_v = Vector::ZERO;
+            let mut neighbor_num = 0;
+            for (k, id_j) in self.rest_neighbors[id].iter().enumerate() {
+                let p_j = index_of[*id_j];
+                let reverse = self.rest_reverse[id][k];
+                if p_j == usize::MAX || reverse == usize::MAX {
+                    continue;
+                }
+
+                let gradient_ij = self.rest_gradients[id][k];
+                let gradient_ji = self.rest_gradients[*id_j][reverse];
+                force += ps.m_v[p_i] * ps.m_v[p_j] * (first_piola[p_i] * gradient_ij - first_piola[p_j] * gradient_ji);
+                relative_v += ps.v[p_j] - ps.v[p_i];
+                neighbor_num += 1;
+            }
+
+            accelerations[p_i] = force / ps.m[p_i];
+            if neighbor_num > 0 {
+                let damping = (material.damping * delta_time).min(1.0) / delta_time;
+                accelerations[p_i] += damping * relative_v / neighbor_num as Real;
+            }
+        }
+
+        accelerations
+    }
+}
+
+
+/// Solver of elastic and plastic solids without fluid, solids of different phases collide
+/// through contact forces
+#[derive(Debug, Clone)]
+pub struct ElasticSolver {
+    ps: ParticleSystem,
+    pub elasticity: Elasticity,
+    /// Stiffness of contact between particles, which aren't connected in rest configuration
+    pub contact_stiffness: Real,
+    pub delta_time: Real,
+}
+
+impl ElasticSolver {
+    pub const G: Vector = vector(0.0, -9.81, 0.0);
+
+    /// Create new elastic solver
+    ///
+    /// # Arguments
+    /// * `material` - material of all particles
+    /// * `delta_time` - length of time step (s)
+    /// * `particle_config` - configuration of particle system
+    pub fn new(material: ElasticMaterial, delta_time: Real, particle_config: Config) -> Self {
+        let phase_num = particle_config.phase.iter().max().map_or(1, |phase| phase + 1);
+        let mut ps = ParticleSystem::new(particle_config);
+        ps.initialize_particle_system();
+        // density of solids isn't computed, it stays at rest density of each particle
+        ps.density = ps.m.iter().zip(ps.m_v.iter()).map(|(m, m_v)| m / m_v).collect();
+
+        let mut solver = ElasticSolver {
+            ps,
+            elasticity: Elasticity::default(),
+            contact_stiffness: 1000.0,
+            delta_time,
+        };
+        solver.elasticity = Elasticity::new(&solver, vec![Some(material); phase_num]);
+        solver.initialize_rigid_bodies();
+
+        solver
+    }
+
+    /// Computes contact acceleration of particle i pushed by particle j and adds it to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_contact_task(&self, p_i: usize, p_j: usize, ret: &mut Vector) {
+        if self.ps.phase[p_i] == self.ps.phase[p_j] {
+            return;
+        }
+
+        let r = self.ps.relative_position(p_i, p_j);
+        let overlap = self.ps.particle_diameter - r.length();
+        if overlap > 0.0 {
+            *ret += self.contact_stiffness * overlap * r.normalize_or_zero();
+        }
+    }
+
+    /// Updates acceleration of each particle from gravity, elasticity and contacts
+    pub fn compute_forces(&mut self) {
+        let accelerations = self.elasticity.compute_accelerations(&self.ps, self.delta_time);
+
+        for p_i in 0..self.particle_num() {
+            let mut d_v = Self::G + accelerations[p_i];
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_contact_task(p_i, p_j, ret), &mut d_v);
+            self.ps.acceleration[p_i] = d_v;
+        }
+    }
+
+    /// For each particle applies its acceleration and velocity
+    pub fn advect(&mut self) {
+        for p_i in 0..self.ps.x.len() {
+            self.ps.v[p_i] += self.delta_time * self.ps.acceleration[p_i];
+            self.ps.x[p_i] += self.delta_time * self.ps.v[p_i];
+        }
+
+        self.ps.advance_boundaries(self.delta_time, Self::G);
+    }
+}
+
+impl Solver for ElasticSolver {
+    fn support_radius(&self) -> Real {
+        self.ps.support_radius
+    }
+
+    fn particle_radius(&self) -> Real {
+        self.ps.particle_radius
+    }
+
+    fn dimensions(&self) -> u32 {
+        self.ps.dimensions
+    }
+
+    fn viscosity(&self) -> Real {
+        0.0
+    }
+
+    fn delta_time(&self) -> Real {
+        self.delta_time
+    }
+
+    fn set_delta_time(&mut self, delta_time: Real) {
+        self.delta_time = delta_time;
+    }
+
+    fn ps(&self) -> &ParticleSystem {
+        &self.ps
+    }
+
+    fn ps_mut(&mut self) -> &mut ParticleSystem {
+        &mut self.ps
+    }
+
+    fn particle_num(&self) -> usize {
+        self.ps.particle_num
+    }
+
+    fn padding(&self) -> Vector {
+        Vector::splat(self.ps.particle_radius)
+    }
+
+    fn domain_size(&self) -> Vector {
+        self.ps.domain_size
+    }
+
+    fn get_density(&self, p_i: usize) -> &Real {
+        &self.ps.density[p_i]
+    }
+
+    fn get_v(&self, p_i: usize) -> Vector {
+        self.ps.v[p_i]
+    }
+
+    fn get_m(&self, p_i: usize) -> &Real {
+        &self.ps.m[p_i]
+    }
+
+    fn get_m_v(&self, p_i: usize) -> &Real {
+        &self.ps.m_v[p_i]
+    }
+
+    fn set_v(&mut self, p_i: usize, vel: Vector) {
+        self.ps.v[p_i] = vel
+    }
+
+    fn domain_start(&self) -> Vector {
+        self.ps.domain_start
+    }
+
+    fn sub_step(&mut self) {
+        self.compute_forces();
+        self.advect();
+    }
+}
+
+
+/// Get elastic material of particle's phase
+///
+/// # Arguments
+/// * `materials` - material of each phase
+/// * `ps` - particle system
+/// * `p_i` - particle id
+fn material(materials: &[Option<ElasticMaterial>], ps: &ParticleSystem, p_i: usize) -> Option<ElasticMaterial> {
+    materials.get(ps.phase[p_i]).copied().flatten()
+}
+
+/// Outer product of two vectors
+fn outer(a: Vector, b: Vector) -> Matrix {
+    Matrix::from_cols(a * b.x, a * b.y, a * b.z)
+}
+
+/// Frobenius norm of matrix
+fn frobenius_norm(m: Matrix) -> Real {
+    (m.x_axis.length_squared() + m.y_axis.length_squared() + m.z_axis.length_squared()).sqrt()
+}
+
+/// Extract rotational part of matrix (Müller et al. 2016)
+///
+/// # Arguments
+/// * `m` - deformation gradient
+/// * `guess` - initial rotation, usually rotation from previous step
+/// * `iterations` - maximal number of iterations
+fn extract_rotation(m: Matrix, guess: Rotation, iterations: usize) -> Rotation {
+    let mut q = guess;
+
+    for _ in 0..iterations {
+        let r = Matrix::from_quat(q);
+        let omega = (r.x_axis.cross(m.x_axis) + r.y_axis.cross(m.y_axis) + r.z_axis.cross(m.z_axis)) / (
+            r.x_axis.dot(m.x_axis) + r.y_axis.dot(m.y_axis) + r.z_axis.dot(m.z_axis)).abs().max(1e-9);
+        let angle = omega.length();
+        if angle < 1e-9 {
+            break;
+        }
+
+        q = (Rotation::from_axis_angle((omega / angle).into(), angle) * q).normalize();
+    }
+
+    q
+}
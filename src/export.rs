@@ -0,0 +1,291 @@
+use std::fs::File;
+use std::io::{BufWriter, Write, Error};
+use std::path::{Path, PathBuf};
+
+use crate::Simulation;
+
+
+/// File format of exported particles
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Legacy ASCII VTK polydata, file per frame
+    Vtk,
+    /// ASCII PLY point cloud, file per frame
+    Ply,
+    /// Classic binary Houdini geometry, file per frame
+    Bgeo,
+    /// Single table with all frames
+    Csv,
+}
+
+impl ExportFormat {
+    /// Get extension of the exported files
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Vtk => "vtk",
+            ExportFormat::Ply => "ply",
+            ExportFormat::Bgeo => "bgeo",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+
+/// Export recorded particles with their ids, colors and channels. Removed particles are skipped.
+/// Formats storing single frame are written into files numbered by frame, e.g. `out_0001.vtk`
+///
+/// # Arguments
+/// * `simulation` - exported recording
+/// * `path` - path to the exported file
+/// * `format` - format of the exported files
+///
+/// # Returns
+/// number of written files or error of writing
+pub fn export_simulation(simulation: &Simulation, path: String, format: ExportFormat) -> Result<usize, Error> {
+    if format == ExportFormat::Csv {
+        let mut file = BufWriter::new(File::create(&path)?);
+        write_csv(&mut file, simulation)?;
+        file.flush()?;
+
+        return Ok(1);
+    }
+
+    for frame in 0..simulation.frame_stop as usize {
+        let mut file = BufWriter::new(File::create(frame_path(Path::new(&path), frame, format))?);
+        match format {
+            ExportFormat::Vtk => write_vtk(&mut file, simulation, frame)?,
+            ExportFormat::Ply => write_ply(&mut file, simulation, frame)?,
+            ExportFormat::Bgeo => write_bgeo(&mut file, simulation, frame)?,
+            ExportFormat::Csv => unreachable!(),
+        }
+        file.flush()?;
+    }
+
+    Ok(simulation.frame_stop as usize)
+}
+
+/// Get path of the file with single frame
+///
+/// # Arguments
+/// * `path` - path given by user
+/// * `frame` - index of the frame
+/// * `format` - format of the file
+fn frame_path(path: &Path, frame: usize, format: ExportFormat) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}_{:04}.{}", stem, frame, format.extension()))
+}
+
+/// Get ids of particles, which are not removed at frame
+///
+/// # Arguments
+/// * `simulation` - recording
+/// * `frame` - index of the frame
+fn visible_particles(simulation: &Simulation, frame: usize) -> Vec<usize> {
+    let start_index = frame * simulation.particle_num as usize;
+    (0..simulation.particle_num as usize)
+        .filter(|id| simulation.frames[start_index + id].is_finite())
+        .collect()
+}
+
+/// Replace characters, which are not allowed in attribute names
+///
+/// # Arguments
+/// * `name` - name of the channel
+fn attribute_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect()
+}
+
+/// Write frame as legacy VTK polydata with vertex cell for each particle
+fn write_vtk(file: &mut impl Write, simulation: &Simulation, frame: usize) -> Result<(), Error> {
+    let particles = visible_particles(simulation, frame);
+    let start_index = frame * simulation.particle_num as usize;
+
+    writeln!(file, "# vtk DataFile Version 3.0")?;
+    writeln!(file, "nikola frame {} time {}", frame, frame as f32 / simulation.fps as f32)?;
+    writeln!(file, "ASCII")?;
+    writeln!(file, "DATASET POLYDATA")?;
+    writeln!(file, "POINTS {} float", particles.len())?;
+    for id in particles.iter() {
+        let position = simulation.frames[start_index + id];
+        writeln!(file, "{} {} {}", position.x, position.y, position.z)?;
+    }
+    writeln!(file, "VERTICES {} {}", particles.len(), 2 * particles.len())?;
+    for index in 0..particles.len() {
+        writeln!(file, "1 {}", index)?;
+    }
+
+    writeln!(file, "POINT_DATA {}", particles.len())?;
+    writeln!(file, "SCALARS id int 1")?;
+    writeln!(file, "LOOKUP_TABLE default")?;
+    for id in particles.iter() {
+        writeln!(file, "{}", id)?;
+    }
+    if !simulation.colors.is_empty() {
+        writeln!(file, "COLOR_SCALARS Cd 3")?;
+        for id in particles.iter() {
+            let color = simulation.colors[*id];
+            writeln!(file, "{} {} {}", color.x, color.y, color.z)?;
+        }
+    }
+    for channel in simulation.channels.iter() {
+        writeln!(file, "SCALARS {} float 1", attribute_name(&channel.name))?;
+        writeln!(file, "LOOKUP_TABLE default")?;
+        for id in particles.iter() {
+            writeln!(file, "{}", channel.values[start_index + id])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write frame as ASCII PLY point cloud, colors are stored as bytes
+fn write_ply(file: &mut impl Write, simulation: &Simulation, frame: usize) -> Result<(), Error> {
+    let particles = visible_particles(simulation, frame);
+    let start_index = frame * simulation.particle_num as usize;
+
+    writeln!(file, "ply")?;
+    writeln!(file, "format ascii 1.0")?;
+    writeln!(file, "comment nikola frame {} time {}", frame, frame as f32 / simulation.fps as f32)?;
+    writeln!(file, "element vertex {}", particles.len())?;
+    writeln!(file, "property float x")?;
+    writeln!(file, "property float y")?;
+    writeln!(file, "property float z")?;
+    writeln!(file, "property int id")?;
+    if !simulation.colors.is_empty() {
+        writeln!(file, "property uchar red")?;
+        writeln!(file, "property uchar green")?;
+        writeln!(file, "property uchar blue")?;
+    }
+    for channel in simulation.channels.iter() {
+        writeln!(file, "property float {}", attribute_name(&channel.name))?;
+    }
+    writeln!(file, "end_header")?;
+
+    for id in particles.iter() {
+        let position = simulation.frames[start_index + id];
+        write!(file, "{} {} {} {}", position.x, position.y, position.z, id)?;
+        if let Some(color) = simulation.colors.get(*id) {
+            let color = (color.clamp(glam::Vec3A::ZERO, glam::Vec3A::ONE) * 255.0).round();
+            write!(file, " {} {} {}", color.x as u8, color.y as u8, color.z as u8)?;
+        }
+        for channel in simulation.channels.iter() {
+            write!(file, " {}", channel.values[start_index + id])?;
+        }
+        writeln!(file)?;
+    }
+
+    Ok(())
+}
+
+/// Write frame as classic big endian Houdini geometry (version 5) with single particle primitive,
+/// the layout follows the writer of Partio library
+fn write_bgeo(file: &mut impl Write, simulation: &Simulation, frame: usize) -> Result<(), Error> {
+    /// Houdini attribute types
+    const FLOAT: i32 = 0;
+    const INT: i32 = 1;
+    const VECTOR: i32 = 5;
+    /// Houdini type of the particle primitive
+    const PARTICLE_PRIMITIVE: i32 = 0x8000;
+
+    fn write_str(file: &mut impl Write, value: &str) -> Result<(), Error> {
+        file.write_all(&(value.len() as u16).to_be_bytes())?;
+        file.write_all(value.as_bytes())
+    }
+
+    fn write_attribute(file: &mut impl Write, name: &str, size: u16, kind: i32) -> Result<(), Error> {
+        write_str(file, name)?;
+        file.write_all(&size.to_be_bytes())?;
+        file.write_all(&kind.to_be_bytes())?;
+        // default values
+        for _ in 0..size {
+            file.write_all(&0_i32.to_be_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    let particles = visible_particles(simulation, frame);
+    let start_index = frame * simulation.particle_num as usize;
+    let has_colors = !simulation.colors.is_empty();
+    let point_attributes = 1 + has_colors as i32 + simulation.channels.len() as i32;
+
+    file.write_all(b"BgeoV")?;
+    // version, points, primitives, point groups, primitive groups, point, vertex, primitive and detail attributes
+    for value in [5, particles.len() as i32, 1, 0, 0, point_attributes, 0, 1, 0] {
+        file.write_all(&value.to_be_bytes())?;
+    }
+
+    write_attribute(file, "id", 1, INT)?;
+    if has_colors {
+        write_attribute(file, "Cd", 3, VECTOR)?;
+    }
+    for channel in simulation.channels.iter() {
+        write_attribute(file, &attribute_name(&channel.name), 1, FLOAT)?;
+    }
+
+    for id in particles.iter() {
+        let position = simulation.frames[start_index + id];
+        for value in [position.x, position.y, position.z, 1.0] {
+            file.write_all(&value.to_be_bytes())?;
+        }
+        file.write_all(&(*id as i32).to_be_bytes())?;
+        if let Some(color) = simulation.colors.get(*id) {
+            for value in color.to_array() {
+                file.write_all(&value.to_be_bytes())?;
+            }
+        }
+        for channel in simulation.channels.iter() {
+            file.write_all(&channel.values[start_index + id].to_be_bytes())?;
+        }
+    }
+
+    // primitive attribute naming the generator of the primitive
+    write_str(file, "generator")?;
+    file.write_all(&1_u16.to_be_bytes())?;
+    file.write_all(&4_i32.to_be_bytes())?;
+    file.write_all(&1_u16.to_be_bytes())?;
+    write_str(file, "nikola")?;
+
+    file.write_all(&PARTICLE_PRIMITIVE.to_be_bytes())?;
+    file.write_all(&(particles.len() as i32).to_be_bytes())?;
+    for index in 0..particles.len() {
+        if particles.len() >= 1 << 16 {
+            file.write_all(&(index as i32).to_be_bytes())?;
+        } else {
+            file.write_all(&(index as u16).to_be_bytes())?;
+        }
+    }
+    file.write_all(&0_i32.to_be_bytes())?;
+
+    file.write_all(&[0x00, 0xff])
+}
+
+/// Write all frames into single table with row for each particle
+fn write_csv(file: &mut impl Write, simulation: &Simulation) -> Result<(), Error> {
+    write!(file, "frame,time,id,x,y,z")?;
+    if !simulation.colors.is_empty() {
+        write!(file, ",r,g,b")?;
+    }
+    for channel in simulation.channels.iter() {
+        write!(file, ",{}", attribute_name(&channel.name))?;
+    }
+    writeln!(file)?;
+
+    for frame in 0..simulation.frame_stop as usize {
+        let start_index = frame * simulation.particle_num as usize;
+        let time = frame as f32 / simulation.fps as f32;
+        for id in visible_particles(simulation, frame) {
+            let position = simulation.frames[start_index + id];
+            write!(file, "{},{},{},{},{},{}", frame, time, id, position.x, position.y, position.z)?;
+            if let Some(color) = simulation.colors.get(id) {
+                write!(file, ",{},{},{}", color.x, color.y, color.z)?;
+            }
+            for channel in simulation.channels.iter() {
+                write!(file, ",{}", channel.values[start_index + id])?;
+            }
+            writeln!(file)?;
+        }
+    }
+
+    Ok(())
+}
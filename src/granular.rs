@@ -0,0 +1,356 @@
+use crate::{Solver, ParticleSystem, Config, Real, Vector, Matrix, vector};
+
+
+/// Granular material solver, elastoplastic stress with Drucker–Prager yield criterion and
+/// Jaumann stress rate (Bui et al. 2008). Stress is positive in tension. Kernel gradients are
+/// corrected (Bonet & Lok 1999), so linear velocity fields are reproduced exactly
+#[derive(Debug, Clone)]
+pub struct GranularSolver {
+    ps: ParticleSystem,
+
+    /// Angle of internal friction (rad), angle of repose of cohesionless sand
+    pub friction_angle: Real,
+    /// Cohesion (Pa), zero for dry sand
+    pub cohesion: Real,
+    /// Young's modulus (Pa), should be high enough to keep the material almost incompressible
+    pub youngs_modulus: Real,
+    pub poisson_ratio: Real,
+    /// Coeficient of artificial viscosity, removes oscillations of the stress
+    pub artificial_viscosity: Real,
+    pub density_0: Real,
+    pub delta_time: Real,
+
+    kernel_correction: Vec<Matrix>,
+    velocity_gradient: Vec<Matrix>,
+}
+
+impl GranularSolver {
+    pub const G: Vector = vector(0.0, -9.81, 0.0);
+
+    /// Create new granular solver
+    ///
+    /// # Arguments
+    /// * `friction_angle` - angle of internal friction (rad)
+    /// * `cohesion` - cohesion of the material (Pa)
+    /// * `delta_time` - length of time step (s)
+    /// * `particle_config` - configuration of particle system
+    pub fn new(friction_angle: Real, cohesion: Real, delta_time: Real, particle_config: Config) -> Self {
+        let density_0 = particle_config.density_0;
+        let mut ps = ParticleSystem::new(particle_config);
+        ps.initialize_particle_system();
+
+        GranularSolver {
+            ps,
+            friction_angle,
+            cohesion,
+            youngs_modulus: 5.0e6,
+            poisson_ratio: 0.3,
+            artificial_viscosity: 0.1,
+            density_0,
+            delta_time,
+            kernel_correction: Vec::new(),
+            velocity_gradient: Vec::new(),
+        }
+    }
+
+    /// Get shear and bulk modulus
+    fn elastic_moduli(&self) -> (Real, Real) {
+        let shear_modulus = self.youngs_modulus / (2.0 * (1.0 + self.poisson_ratio));
+        let bulk_modulus = self.youngs_modulus / (3.0 * (1.0 - 2.0 * self.poisson_ratio));
+
+        (shear_modulus, bulk_modulus)
+    }
+
+    /// Get speed of sound in the material, used by artificial viscosity
+    fn sound_speed(&self) -> Real {
+        let (_, bulk_modulus) = self.elastic_moduli();
+        (bulk_modulus / self.density_0).sqrt()
+    }
+
+    /// Get Drucker–Prager constants (alpha, k) matched to Mohr–Coulomb under plane strain
+    fn drucker_prager(&self) -> (Real, Real) {
+        let tan_phi = self.friction_angle.tan();
+        let denominator = (9.0 + 12.0 * tan_phi * tan_phi).sqrt();
+
+        (tan_phi / denominator, 3.0 * self.cohesion / denominator)
+    }
+
+    /// Computes density for particle i influenced by j and adds the result to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_densities_task(&self, p_i: usize, p_j: usize, ret: &mut Real) {
+        let r = self.ps.relative_position(p_i, p_j);
+
+        *ret += self.ps.m_v[p_j] * self.cubic_kernel(r.length());
+    }
+
+    /// Updates density for each particle
+    pub fn compute_densities(&mut self) {
+        for p_i in 0..self.particle_num() {
+            let mut density_i = self.ps.m_v[p_i] * self.cubic_kernel(0.0);
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_densities_task(p_i, p_j, ret), &mut density_i);
+            self.ps.density[p_i] = density_i * self.density_0;
+        }
+    }
+
+    /// Computes contribution of particle j to the moment matrix of particle i and adds it to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_kernel_correction_task(&self, p_i: usize, p_j: usize, ret: &mut Matrix) {
+        let r = self.ps.relative_position(p_i, p_j);
+        let x_ji = -self.ps.m[p_j] / self.ps.density[p_j] * r;
+        let gradient = self.cubic_kernel_derivative(r);
+
+        *ret += Matrix::from_cols(x_ji * gradient.x, x_ji * gradient.y, x_ji * gradient.z);
+    }
+
+    /// Updates kernel gradient correction of each particle, particles with degenerate
+    /// neighborhood get only isotropic correction
+    pub fn compute_kernel_corrections(&mut self) {
+        self.kernel_correction.resize(self.particle_num(), Matrix::IDENTITY);
+        for p_i in 0..self.particle_num() {
+            let mut moment = Matrix::ZERO;
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_kernel_correction_task(p_i, p_j, ret), &mut moment);
+
+            let dimensions = self.dimensions();
+            let scale = trace(moment) / dimensions as Real;
+            // 2D neighborhood has no extent along z, which is left uncorrected
+            if dimensions == 2 {
+                moment.z_axis.z = 1.0;
+            }
+            self.kernel_correction[p_i] = if moment.determinant() > 0.1 * scale.powi(dimensions as i32) {
+                moment.inverse().transpose()
+            } else if scale > Real::EPSILON {
+                Matrix::IDENTITY * (1.0 / scale)
+            } else {
+                Matrix::IDENTITY
+            };
+        }
+    }
+
+    /// Get corrected kernel gradient, average correction of both particles keeps it antisymmetric
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `r` - difference vector between the particles
+    fn corrected_gradient(&self, p_i: usize, p_j: usize, r: Vector) -> Vector {
+        0.5 * (self.kernel_correction[p_i] + self.kernel_correction[p_j]) * self.cubic_kernel_derivative(r)
+    }
+
+    /// Computes contribution of particle j to velocity gradient of particle i and adds it to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_velocity_gradient_task(&self, p_i: usize, p_j: usize, ret: &mut Matrix) {
+        let r = self.ps.relative_position(p_i, p_j);
+        let volume = self.ps.m[p_j] / self.ps.density[p_j];
+        let v_ji = volume * (self.ps.v[p_j] - self.ps.v[p_i]);
+        let gradient = self.kernel_correction[p_i] * self.cubic_kernel_derivative(r);
+
+        *ret += Matrix::from_cols(v_ji * gradient.x, v_ji * gradient.y, v_ji * gradient.z);
+    }
+
+    /// Integrates stress of each particle by Jaumann rate and returns it to the yield surface
+    pub fn update_stresses(&mut self) {
+        self.compute_kernel_corrections();
+        self.velocity_gradient.resize(self.particle_num(), Matrix::ZERO);
+        for p_i in 0..self.particle_num() {
+            let mut gradient = Matrix::ZERO;
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_velocity_gradient_task(p_i, p_j, ret), &mut gradient);
+            self.velocity_gradient[p_i] = gradient;
+        }
+
+        let (shear_modulus, bulk_modulus) = self.elastic_moduli();
+        let (alpha, k) = self.drucker_prager();
+
+        for p_i in 0..self.particle_num() {
+            let gradient = self.velocity_gradient[p_i];
+            let strain_rate = 0.5 * (gradient + gradient.transpose());
+            let spin = 0.5 * (gradient - gradient.transpose());
+            let volumetric_rate = trace(strain_rate);
+            let deviatoric_rate = strain_rate - volumetric_rate / 3.0 * Matrix::IDENTITY;
+
+            let stress = self.ps.stress[p_i];
+            let stress_rate = 2.0 * shear_modulus * deviatoric_rate
+                + bulk_modulus * volumetric_rate * Matrix::IDENTITY
+                + spin * stress - stress * spin;
+            let mut stress = stress + self.delta_time * stress_rate;
+
+            // tension cracking, mean stress can't exceed the apex of the yield cone
+            let mut i_1 = trace(stress);
+            if alpha > 0.0 && i_1 > k / alpha {
+                stress -= (i_1 - k / alpha) / 3.0 * Matrix::IDENTITY;
+                i_1 = k / alpha;
+            } else if alpha == 0.0 && i_1 > 0.0 {
+                stress -= i_1 / 3.0 * Matrix::IDENTITY;
+                i_1 = 0.0;
+            }
+
+            // scale deviatoric stress back to the yield surface
+            let deviatoric = stress - i_1 / 3.0 * Matrix::IDENTITY;
+            let j_2 = 0.5 * double_dot(deviatoric, deviatoric);
+            let limit = k - alpha * i_1;
+            if j_2.sqrt() > limit {
+                let scale = limit.max(0.0) / j_2.sqrt();
+                stress = scale * deviatoric + i_1 / 3.0 * Matrix::IDENTITY;
+            }
+
+            self.ps.stress[p_i] = stress;
+        }
+    }
+
+    /// Computes acceleration of particle i from stress divergence and artificial viscosity of
+    /// particle j and adds the result to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_forces_task(&self, p_i: usize, p_j: usize, ret: &mut Vector) {
+        let r = self.ps.relative_position(p_i, p_j);
+        let gradient = self.corrected_gradient(p_i, p_j, r);
+        let density_i = self.ps.density[p_i];
+        let density_j = self.ps.density[p_j];
+
+        let stress = self.ps.stress[p_i] * density_i.powi(-2) + self.ps.stress[p_j] * density_j.powi(-2);
+        *ret += self.ps.m[p_j] * (stress * gradient);
+
+        // Monaghan artificial viscosity, acts only on approaching particles
+        let v_xy = (self.ps.v[p_i] - self.ps.v[p_j]).dot(r);
+        if v_xy < 0.0 {
+            let h = self.support_radius();
+            let mu = h * v_xy / (r.length_squared() + 0.01 * h * h);
+            let viscosity = -self.artificial_viscosity * self.sound_speed() * mu / (0.5 * (density_i + density_j));
+            *ret -= self.ps.m[p_j] * viscosity * gradient;
+        }
+    }
+
+    /// Updates acceleration of each particle
+    pub fn compute_forces(&mut self) {
+        for p_i in 0..self.particle_num() {
+            let mut d_v = Self::G;
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_forces_task(p_i, p_j, ret), &mut d_v);
+            self.ps.acceleration[p_i] = d_v;
+        }
+    }
+
+    /// For each particle applies its acceleration and velocity
+    pub fn advect(&mut self) {
+        for p_i in 0..self.ps.x.len() {
+            self.ps.v[p_i] += self.delta_time * self.ps.acceleration[p_i];
+            self.ps.x[p_i] += self.delta_time * self.ps.v[p_i];
+        }
+
+        self.ps.advance_boundaries(self.delta_time, Self::G);
+    }
+}
+
+impl Solver for GranularSolver {
+    fn support_radius(&self) -> Real {
+        self.ps.support_radius
+    }
+
+    fn particle_radius(&self) -> Real {
+        self.ps.particle_radius
+    }
+
+    fn dimensions(&self) -> u32 {
+        self.ps.dimensions
+    }
+
+    fn viscosity(&self) -> Real {
+        0.0
+    }
+
+    fn delta_time(&self) -> Real {
+        self.delta_time
+    }
+
+    fn set_delta_time(&mut self, delta_time: Real) {
+        self.delta_time = delta_time;
+    }
+
+    fn ps(&self) -> &ParticleSystem {
+        &self.ps
+    }
+
+    fn ps_mut(&mut self) -> &mut ParticleSystem {
+        &mut self.ps
+    }
+
+    fn particle_num(&self) -> usize {
+        self.ps.particle_num
+    }
+
+    fn padding(&self) -> Vector {
+        Vector::splat(self.ps.particle_radius)
+    }
+
+    fn domain_size(&self) -> Vector {
+        self.ps.domain_size
+    }
+
+    fn get_density(&self, p_i: usize) -> &Real {
+        &self.ps.density[p_i]
+    }
+
+    fn get_v(&self, p_i: usize) -> Vector {
+        self.ps.v[p_i]
+    }
+
+    fn get_m(&self, p_i: usize) -> &Real {
+        &self.ps.m[p_i]
+    }
+
+    fn get_m_v(&self, p_i: usize) -> &Real {
+        &self.ps.m_v[p_i]
+    }
+
+    fn set_v(&mut self, p_i: usize, vel: Vector) {
+        self.ps.v[p_i] = vel
+    }
+
+    fn domain_start(&self) -> Vector {
+        self.ps.domain_start
+    }
+
+    fn sub_step(&mut self) {
+        self.compute_densities();
+        self.update_stresses();
+        self.compute_forces();
+        self.advect();
+    }
+
+    /// Inelastic collision with Coulomb friction of the wall, so the material can pile up
+    fn simulate_collisions(&mut self, p_i: usize, vec: Vector, wall_v: Vector) {
+        let relative_v = self.get_v(p_i) - wall_v;
+        let normal_v = relative_v.dot(vec);
+        if normal_v <= 0.0 {
+            return;
+        }
+
+        let tangent_v = relative_v - normal_v * vec;
+        let friction = (1.0 - self.friction_angle.tan() * normal_v / tangent_v.length().max(Real::EPSILON)).max(0.0);
+        self.set_v(p_i, wall_v + friction * tangent_v);
+    }
+}
+
+
+/// Trace of matrix
+fn trace(m: Matrix) -> Real {
+    m.x_axis.x + m.y_axis.y + m.z_axis.z
+}
+
+/// Double dot product (sum of elementwise products) of two matrices
+fn double_dot(a: Matrix, b: Matrix) -> Real {
+    a.x_axis.dot(b.x_axis) + a.y_axis.dot(b.y_axis) + a.z_axis.dot(b.z_axis)
+}
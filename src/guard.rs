@@ -0,0 +1,182 @@
+use std::fmt;
+
+use crate::{Solver, ParticleSystem, Real};
+
+
+/// Action taken, when the guard detects invalid state of the simulation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GuardPolicy {
+    /// Stop the simulation and report offending particles
+    Abort,
+    /// Restore the last valid state, halve time step and retry
+    Rollback,
+}
+
+
+/// Kind of invalid state detected by the guard
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    /// Position or velocity is NaN or infinite
+    NotFinite,
+    /// Particle left the grid of the domain
+    OutsideDomain,
+    /// Relative density error exceeded threshold
+    DensityError,
+}
+
+
+/// Report of invalid state, particles are identified by their original ids
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardReport {
+    pub violation: Violation,
+    /// Original ids of offending particles
+    pub ids: Vec<usize>,
+    /// Simulation time of the invalid state
+    pub time: Real,
+}
+
+impl fmt::Display for GuardReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let violation = match self.violation {
+            Violation::NotFinite => "non-finite position or velocity",
+            Violation::OutsideDomain => "particles outside of the domain",
+            Violation::DensityError => "density error above threshold",
+        };
+        let shown = self.ids.len().min(10);
+        let ids = self.ids[..shown].iter().map(|id| id.to_string()).collect::<Vec<String>>().join(", ");
+        let rest = if self.ids.len() > shown { format!(" and {} more", self.ids.len() - shown) } else { String::new() };
+
+        write!(f, "{} at {}s, particles: {}{}", violation, self.time, ids, rest)
+    }
+}
+
+impl std::error::Error for GuardReport {}
+
+
+/// Checks state of the simulation after every step and handles explosions
+#[derive(Debug, Clone, Copy)]
+pub struct Guard {
+    pub policy: GuardPolicy,
+    /// Maximal relative deviation of density from rest density
+    pub max_density_error: Real,
+    /// Maximal number of time step halvings, before the rollback gives up
+    pub max_retries: u32,
+}
+
+impl Guard {
+    /// Create guard with default thresholds
+    ///
+    /// # Arguments
+    /// * `policy` - action taken on invalid state
+    pub fn new(policy: GuardPolicy) -> Self {
+        Guard { policy, max_density_error: 1.0, max_retries: 4 }
+    }
+
+    /// Check particles for non-finite values, positions outside the grid and density error. Density
+    /// is estimated at the current positions, so that explosion is detected by the step which
+    /// caused it, rest density of each particle is given by ratio of its mass and volume
+    ///
+    /// # Arguments
+    /// * `solver` - checked solver
+    pub fn check<S: Solver + ?Sized>(&self, solver: &S) -> Result<(), GuardReport> {
+        let offending = |ps: &ParticleSystem, predicate: &dyn Fn(usize) -> bool| {
+            (0..ps.particle_num).filter(|p_i| predicate(*p_i)).map(|p_i| ps.ids[p_i]).collect::<Vec<usize>>()
+        };
+
+        let ps = solver.ps();
+        let checks: [(Violation, &dyn Fn(usize) -> bool); 2] = [
+            (Violation::NotFinite, &|p_i| !ps.x[p_i].is_finite() || !ps.v[p_i].is_finite()),
+            (Violation::OutsideDomain, &|p_i| !ps.is_inside_grid(ps.x[p_i])),
+        ];
+        for (violation, predicate) in checks {
+            let ids = offending(ps, predicate);
+            if !ids.is_empty() {
+                return Err(GuardReport { violation, ids, time: ps.time });
+            }
+        }
+
+        let density = Self::estimate_density(solver);
+        let ids = offending(ps, &|p_i| {
+            let density_0 = ps.m[p_i] / ps.m_v[p_i];
+            let density_error = ((density[p_i] - density_0) / density_0).abs();
+            density_error.is_nan() || density_error > self.max_density_error
+        });
+        if !ids.is_empty() {
+            return Err(GuardReport { violation: Violation::DensityError, ids, time: ps.time });
+        }
+
+        Ok(())
+    }
+
+    /// Estimate density of each particle by kernel sum over neighbouring particles and boundary
+    /// particles of rigid bodies. Particles moved by the step are looked up in separately sorted
+    /// cells, so their order stays valid for arrays of the solver
+    ///
+    /// # Arguments
+    /// * `solver` - examined solver
+    fn estimate_density<S: Solver + ?Sized>(solver: &S) -> Vec<Real> {
+        let ps = solver.ps();
+        let cells = ps.grid_cells();
+        let mut volume = vec![0.0; ps.particle_num];
+        for (body_id, body) in ps.rigid_bodies.iter().enumerate() {
+            for (p_b, x_b) in body.boundary_world.iter().enumerate() {
+                ps.for_all_particles_near_in(&cells, *x_b, |p_i, r| {
+                    volume[p_i] += ps.rigid_bodies[body_id].boundary_volume[p_b] * solver.cubic_kernel(r.length());
+                });
+            }
+        }
+
+        for (p_i, volume_i) in volume.iter_mut().enumerate() {
+            ps.for_all_particles_near_in(&cells, ps.x[p_i], |p_j, r| *volume_i += ps.m_v[p_j] * solver.cubic_kernel(r.length()));
+        }
+
+        volume.iter().enumerate().map(|(p_i, volume_i)| volume_i * ps.m[p_i] / ps.m_v[p_i]).collect()
+    }
+
+    /// Step the solver and check the result. With rollback policy invalid step is repeated from
+    /// the previous state by 2, 4, 8... sub steps of shorter time step, so the simulated time stays
+    /// the same. Time step of the solver is restored afterwards
+    ///
+    /// # Arguments
+    /// * `solver` - guarded solver
+    ///
+    /// # Returns
+    /// report of the invalid state, when the step couldn't be done
+    pub fn step<S: Solver + Clone>(&self, solver: &mut S) -> Result<(), GuardReport> {
+        let backup = match self.policy {
+            GuardPolicy::Rollback => Some(solver.clone()),
+            GuardPolicy::Abort => None,
+        };
+
+        solver.step();
+        let mut report = match self.check(solver) {
+            Ok(()) => return Ok(()),
+            Err(report) => report,
+        };
+        let backup = match backup {
+            Some(backup) => backup,
+            None => return Err(report),
+        };
+
+        let delta_time = backup.delta_time();
+        for retry in 1..=self.max_retries {
+            *solver = backup.clone();
+            let sub_steps = 1 << retry;
+            solver.set_delta_time(delta_time / sub_steps as Real);
+
+            let result = (0..sub_steps).try_for_each(|_sub_step| {
+                solver.step();
+                self.check(solver)
+            });
+            solver.set_delta_time(delta_time);
+
+            match result {
+                Ok(()) => return Ok(()),
+                Err(retry_report) => report = retry_report,
+            }
+        }
+
+        *solver = backup;
+        Err(report)
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+use std::fs::write;
+
+use glam::Vec3A;
+
+use crate::Simulation;
+
+
+/// Statistics of particles at one recorded frame, removed particles are skipped
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStatistics {
+    /// Time of the frame (s)
+    pub time: f32,
+    /// Number of particles, which were not removed
+    pub particles: usize,
+    /// Minimal corner of box bounding the particles
+    pub min: Vec3A,
+    /// Maximal corner of box bounding the particles
+    pub max: Vec3A,
+    /// Average position of particles, all particles have the same mass
+    pub center_of_mass: Vec3A,
+    /// Average speed given by difference of positions in neighbouring frames
+    pub average_speed: f32,
+    pub max_speed: f32,
+    /// Number of positions, which are NaN
+    pub nan: usize,
+    /// Number of particles outside of the domain
+    pub out_of_domain: usize,
+}
+
+
+/// Statistics of recorded simulation computed without the solver, used to check recordings
+/// made in terminal
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    /// Statistics of each frame
+    pub frames: Vec<FrameStatistics>,
+    /// Starting and ending point of the domain particles should stay in
+    pub domain: (Vec3A, Vec3A),
+}
+
+impl Inspection {
+    /// Compute statistics of each frame, velocity is computed by central difference of positions
+    /// and by one sided difference at the first and last frame
+    ///
+    /// # Arguments
+    /// * `simulation` - inspected recording, incomplete frames are skipped
+    /// * `domain` - starting and ending point of the simulation domain
+    pub fn compute(simulation: &Simulation, domain: (Vec3A, Vec3A)) -> Self {
+        let particle_num = simulation.particle_num as usize;
+        let frame_num = simulation.recorded_frames();
+        let position = |frame: usize, id: usize| simulation.frames[frame * particle_num + id];
+
+        let frames = (0..frame_num)
+            .map(|frame| {
+                let previous = frame.saturating_sub(1);
+                let next = (frame + 1).min(frame_num - 1);
+                let delta_time = (next - previous) as f32 / simulation.fps as f32;
+
+                let mut statistics = FrameStatistics {
+                    time: frame as f32 / simulation.fps as f32,
+                    particles: 0,
+                    min: Vec3A::splat(f32::MAX),
+                    max: Vec3A::splat(f32::MIN),
+                    center_of_mass: Vec3A::ZERO,
+                    average_speed: 0.0,
+                    max_speed: 0.0,
+                    nan: 0,
+                    out_of_domain: 0,
+                };
+                let mut speed_count = 0;
+
+                for id in 0..particle_num {
+                    let x = position(frame, id);
+                    if x.is_nan() {
+                        statistics.nan += 1;
+                        continue;
+                    }
+                    if !x.is_finite() {
+                        continue;
+                    }
+
+                    statistics.particles += 1;
+                    statistics.min = statistics.min.min(x);
+                    statistics.max = statistics.max.max(x);
+                    statistics.center_of_mass += x;
+                    if x.cmplt(domain.0).any() || x.cmpgt(domain.1).any() {
+                        statistics.out_of_domain += 1;
+                    }
+
+                    let difference = position(next, id) - position(previous, id);
+                    if delta_time > 0.0 && difference.is_finite() {
+                        let speed = difference.length() / delta_time;
+                        statistics.average_speed += speed;
+                        statistics.max_speed = statistics.max_speed.max(speed);
+                        speed_count += 1;
+                    }
+                }
+
+                if statistics.particles > 0 {
+                    statistics.center_of_mass /= statistics.particles as f32;
+                }
+                if speed_count > 0 {
+                    statistics.average_speed /= speed_count as f32;
+                }
+
+                statistics
+            })
+            .collect();
+
+        Inspection { frames, domain }
+    }
+
+    /// Get box bounding particles over all frames
+    ///
+    /// # Returns
+    /// minimal and maximal corner of the box
+    pub fn bounds(&self) -> (Vec3A, Vec3A) {
+        self.frames
+            .iter()
+            .filter(|frame| frame.particles > 0)
+            .fold((Vec3A::splat(f32::MAX), Vec3A::splat(f32::MIN)), |(min, max), frame| (min.min(frame.min), max.max(frame.max)))
+    }
+
+    /// Get first frame with NaN positions
+    pub fn first_nan_frame(&self) -> Option<usize> {
+        self.frames.iter().position(|frame| frame.nan > 0)
+    }
+
+    /// Get first frame with particles outside of the domain
+    pub fn first_out_of_domain_frame(&self) -> Option<usize> {
+        self.frames.iter().position(|frame| frame.out_of_domain > 0)
+    }
+
+    /// Check that no position is NaN or outside of the domain
+    pub fn is_valid(&self) -> bool {
+        self.first_nan_frame().is_none() && self.first_out_of_domain_frame().is_none()
+    }
+
+    /// Write statistics of frames as CSV file with header
+    ///
+    /// # Arguments
+    /// * `path` - path to the target file
+    pub fn save(&self, path: String) -> Result<(), std::io::Error> {
+        let mut csv = String::from(
+            "time,particles,min_x,min_y,min_z,max_x,max_y,max_z,center_of_mass_x,center_of_mass_y,center_of_mass_z,\
+            average_speed,max_speed,nan,out_of_domain\n"
+        );
+
+        for frame in self.frames.iter() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                frame.time,
+                frame.particles,
+                frame.min.x,
+                frame.min.y,
+                frame.min.z,
+                frame.max.x,
+                frame.max.y,
+                frame.max.z,
+                frame.center_of_mass.x,
+                frame.center_of_mass.y,
+                frame.center_of_mass.z,
+                frame.average_speed,
+                frame.max_speed,
+                frame.nan,
+                frame.out_of_domain,
+            ));
+        }
+
+        write(path, csv)
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (min, max) = self.bounds();
+        writeln!(f, "inspected frames: {}", self.frames.len())?;
+        if let (Some(first), Some(last)) = (self.frames.first(), self.frames.last()) {
+            writeln!(f, "particles: {} -> {}", first.particles, last.particles)?;
+            writeln!(f, "center of mass: {} -> {}", first.center_of_mass, last.center_of_mass)?;
+        }
+        writeln!(f, "bounds: {} - {}", min, max)?;
+
+        let fastest = self.frames
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.max_speed.total_cmp(&b.max_speed));
+        if let Some((frame, statistics)) = fastest {
+            writeln!(f, "max speed: {} at frame {} ({}s)", statistics.max_speed, frame, statistics.time)?;
+        }
+
+        match self.first_nan_frame() {
+            Some(frame) => writeln!(f, "NaN positions: {} since frame {}", self.frames.iter().map(|frame| frame.nan).sum::<usize>(), frame)?,
+            None => writeln!(f, "NaN positions: 0")?,
+        }
+        match self.first_out_of_domain_frame() {
+            Some(frame) => write!(
+                f,
+                "out of domain {} - {}: {} since frame {}",
+                self.domain.0,
+                self.domain.1,
+                self.frames.iter().map(|frame| frame.out_of_domain).sum::<usize>(),
+                frame
+            ),
+            None => write!(f, "out of domain {} - {}: 0", self.domain.0, self.domain.1),
+        }
+    }
+}
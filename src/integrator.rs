@@ -0,0 +1,225 @@
+use crate::{Solver, Real, Vector};
+
+
+/// Time integration scheme of particle positions and velocities
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    /// Semi-implicit Euler, velocity is updated first and the new velocity moves the particle
+    SymplecticEuler,
+    /// Kick-drift-kick leapfrog, accelerations are evaluated at the start and at the end of the step
+    Leapfrog,
+    /// Velocity Verlet, acceleration of the previous step is reused, so it needs one evaluation per step
+    VelocityVerlet,
+    /// Explicit midpoint method (Runge-Kutta of second order)
+    RungeKutta2,
+    /// Classic Runge-Kutta method of fourth order
+    RungeKutta4,
+}
+
+impl Integrator {
+    /// Get weights of accelerations from each evaluation in the resulting velocity change,
+    /// forces applied to rigid bodies are averaged with the same weights
+    fn weights(&self) -> &'static [Real] {
+        match self {
+            Integrator::SymplecticEuler => &[1.0],
+            Integrator::Leapfrog => &[0.5, 0.5],
+            Integrator::VelocityVerlet => &[1.0],
+            Integrator::RungeKutta2 => &[0.0, 1.0],
+            Integrator::RungeKutta4 => &[1.0 / 6.0, 1.0 / 3.0, 1.0 / 3.0, 1.0 / 6.0],
+        }
+    }
+
+    /// Get number of acceleration evaluations per time step
+    pub fn evaluations(&self) -> usize {
+        self.weights().len()
+    }
+
+    /// Move particles of the solver by one time step. Before each evaluation except for the first
+    /// one, which is prepared by solver step, particles are kept inside the domain and sorted into the grid
+    ///
+    /// # Arguments
+    /// * `solver` - integrated solver
+    /// * `state` - state of the integrator kept between steps
+    /// * `delta_time` - length of time step
+    /// * `evaluate` - computes acceleration of each particle from current positions and velocities
+    pub fn step<S: Solver>(&self, solver: &mut S, state: &mut IntegratorState, delta_time: Real, evaluate: fn(&mut S)) {
+        let weights = self.weights();
+        state.body_forces = vec![(Vector::ZERO, Vector::ZERO); solver.ps().rigid_bodies.len()];
+
+        match self {
+            Integrator::SymplecticEuler => {
+                state.evaluate(solver, evaluate, weights[0]);
+                let ps = solver.ps_mut();
+                for p_i in 0..ps.particle_num {
+                    ps.v[p_i] += delta_time * ps.acceleration[p_i];
+                    ps.x[p_i] += delta_time * ps.v[p_i];
+                }
+            }
+            Integrator::Leapfrog => {
+                state.evaluate(solver, evaluate, weights[0]);
+                let ps = solver.ps_mut();
+                for p_i in 0..ps.particle_num {
+                    ps.v[p_i] += 0.5 * delta_time * ps.acceleration[p_i];
+                    ps.x[p_i] += delta_time * ps.v[p_i];
+                }
+
+                prepare_evaluation(solver);
+                state.evaluate(solver, evaluate, weights[1]);
+                let ps = solver.ps_mut();
+                for p_i in 0..ps.particle_num {
+                    ps.v[p_i] += 0.5 * delta_time * ps.acceleration[p_i];
+                }
+            }
+            Integrator::VelocityVerlet => {
+                if !state.has_acceleration {
+                    state.evaluate(solver, evaluate, 0.0);
+                }
+                state.store(solver);
+
+                // velocity is predicted for velocity dependent forces
+                let ps = solver.ps_mut();
+                for p_i in 0..ps.particle_num {
+                    ps.x[p_i] += delta_time * ps.v[p_i] + 0.5 * delta_time * delta_time * ps.acceleration[p_i];
+                    ps.v[p_i] += delta_time * ps.acceleration[p_i];
+                }
+
+                prepare_evaluation(solver);
+                state.evaluate(solver, evaluate, weights[0]);
+                let ps = solver.ps_mut();
+                for p_i in 0..ps.particle_num {
+                    let id = ps.ids[p_i];
+                    ps.v[p_i] = state.v[id] + 0.5 * delta_time * (state.acceleration[id] + ps.acceleration[p_i]);
+                }
+                state.has_acceleration = true;
+            }
+            Integrator::RungeKutta2 => {
+                self.runge_kutta(solver, state, delta_time, evaluate, &[0.0, 0.5]);
+            }
+            Integrator::RungeKutta4 => {
+                self.runge_kutta(solver, state, delta_time, evaluate, &[0.0, 0.5, 0.5, 1.0]);
+            }
+        }
+
+        // acceleration is invalidated by other integrators
+        if *self != Integrator::VelocityVerlet {
+            state.has_acceleration = false;
+        }
+
+        for (body, (force, torque)) in solver.ps_mut().rigid_bodies.iter_mut().zip(state.body_forces.iter()) {
+            body.force = *force;
+            body.torque = *torque;
+        }
+    }
+
+    /// Explicit Runge-Kutta method, each stage depends only on the previous one
+    ///
+    /// # Arguments
+    /// * `solver` - integrated solver
+    /// * `state` - state of the integrator
+    /// * `delta_time` - length of time step
+    /// * `evaluate` - computes acceleration of each particle
+    /// * `nodes` - fraction of the time step, at which is each stage evaluated
+    fn runge_kutta<S: Solver>(&self, solver: &mut S, state: &mut IntegratorState, delta_time: Real, evaluate: fn(&mut S), nodes: &[Real]) {
+        let weights = self.weights();
+        state.store(solver);
+        state.dx.iter_mut().for_each(|dx| *dx = Vector::ZERO);
+        state.dv.iter_mut().for_each(|dv| *dv = Vector::ZERO);
+
+        for stage in 0..nodes.len() {
+            if stage > 0 {
+                prepare_evaluation(solver);
+            }
+            state.evaluate(solver, evaluate, weights[stage]);
+
+            let ps = solver.ps_mut();
+            for p_i in 0..ps.particle_num {
+                let id = ps.ids[p_i];
+                state.dx[id] += weights[stage] * ps.v[p_i];
+                state.dv[id] += weights[stage] * ps.acceleration[p_i];
+
+                if let Some(node) = nodes.get(stage + 1) {
+                    ps.x[p_i] = state.x[id] + node * delta_time * ps.v[p_i];
+                    ps.v[p_i] = state.v[id] + node * delta_time * ps.acceleration[p_i];
+                }
+            }
+        }
+
+        let ps = solver.ps_mut();
+        for p_i in 0..ps.particle_num {
+            let id = ps.ids[p_i];
+            ps.x[p_i] = state.x[id] + delta_time * state.dx[id];
+            ps.v[p_i] = state.v[id] + delta_time * state.dv[id];
+        }
+    }
+}
+
+
+/// Resolve collisions of moved particles with kinematic bodies and the domain and sort them into
+/// the grid, as solver step does before the first evaluation
+///
+/// # Arguments
+/// * `solver` - integrated solver
+fn prepare_evaluation<S: Solver>(solver: &mut S) {
+    solver.ps_mut().collide_with_kinematic_bodies();
+    if solver.dimensions() == 2 {
+        solver.enforce_boundary_2d();
+    } else {
+        solver.enforce_boundary_3d();
+    }
+    solver.ps_mut().initialize_particle_system();
+}
+
+
+/// State of particles at the start of the step, values are indexed by original particle id,
+/// because particles are sorted between evaluations
+#[derive(Debug, Clone, Default)]
+pub struct IntegratorState {
+    x: Vec<Vector>,
+    v: Vec<Vector>,
+    acceleration: Vec<Vector>,
+    dx: Vec<Vector>,
+    dv: Vec<Vector>,
+    /// Whether particles hold acceleration of the previous step (velocity Verlet)
+    has_acceleration: bool,
+    /// Weighted forces and torques applied to rigid bodies during the step
+    body_forces: Vec<(Vector, Vector)>,
+}
+
+impl IntegratorState {
+    /// Store positions, velocities and accelerations of particles
+    ///
+    /// # Arguments
+    /// * `solver` - integrated solver
+    fn store<S: Solver>(&mut self, solver: &S) {
+        let ps = solver.ps();
+        let size = ps.ids.iter().max().map_or(0, |id| id + 1);
+        for values in [&mut self.x, &mut self.v, &mut self.acceleration, &mut self.dx, &mut self.dv] {
+            values.resize(size, Vector::ZERO);
+        }
+
+        for p_i in 0..ps.particle_num {
+            let id = ps.ids[p_i];
+            self.x[id] = ps.x[p_i];
+            self.v[id] = ps.v[p_i];
+            self.acceleration[id] = ps.acceleration[p_i];
+        }
+    }
+
+    /// Evaluate accelerations, forces which the evaluation applied to rigid bodies are weighted and
+    /// accumulated
+    ///
+    /// # Arguments
+    /// * `solver` - integrated solver
+    /// * `evaluate` - computes acceleration of each particle
+    /// * `weight` - weight of the evaluation
+    fn evaluate<S: Solver>(&mut self, solver: &mut S, evaluate: fn(&mut S), weight: Real) {
+        evaluate(solver);
+
+        for (body, (force, torque)) in solver.ps_mut().rigid_bodies.iter_mut().zip(self.body_forces.iter_mut()) {
+            *force += weight * body.force;
+            *torque += weight * body.torque;
+            body.force = Vector::ZERO;
+            body.torque = Vector::ZERO;
+        }
+    }
+}
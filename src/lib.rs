@@ -1,19 +1,53 @@
+mod precision;
 mod config;
 mod solver;
 mod wcsph;
 mod particles_system;
 mod simulation;
+mod boundary;
+mod rigid_body;
+mod motion;
+mod phase;
+mod viscosity;
+mod thermal;
+mod diffuse;
+mod elastic;
+mod granular;
+mod integrator;
+mod diagnostics;
+mod guard;
+mod scene;
+mod export;
+mod inspection;
+mod playback;
 
 use std::fs::{self, ReadDir, DirEntry};
 use std::path::PathBuf;
 use std::thread::sleep;
 use std::time::{Instant, Duration};
 
+pub use precision::*;
 pub use config::*;
 pub use solver::*;
 pub use wcsph::*;
 pub use particles_system::*;
 pub use simulation::*;
+pub use boundary::*;
+pub use rigid_body::*;
+pub use motion::*;
+pub use phase::*;
+pub use viscosity::*;
+pub use thermal::*;
+pub use diffuse::*;
+pub use elastic::*;
+pub use granular::*;
+pub use integrator::*;
+pub use diagnostics::*;
+pub use guard::*;
+pub use scene::*;
+pub use export::*;
+pub use inspection::*;
+pub use playback::*;
 
 use glam::{vec3a, Vec3A};
 use fluid_renderer::*;
@@ -55,23 +89,26 @@ pub fn load_files(path: PathBuf) -> Vec<DirEntry> {
 }
 
 /// Start application in player mode
-pub fn run_simulation(simulation_path: String, fps: u32, particle_size: f32) {
+///
+/// # Arguments
+/// * `simulation_path` - path to the replayed recording
+/// * `fps` - maximal number of drawn frames per second, the replay is paced by real time
+/// * `particle_size` - radius of drawn particles
+/// * `speed` - speed of the replay relative to real time, negative speed plays backwards
+/// * `interpolation` - computation of positions between recorded frames
+/// * `mode` - behaviour of the replay at the ends of recording
+pub fn run_simulation(simulation_path: String, fps: u32, particle_size: f32, speed: f32, interpolation: Interpolation, mode: PlaybackMode) {
     let InitOutput{event_loop, window, aspect_ratio} = init(); 
     let shader_source = fluid_renderer::wgpu::ShaderSource::Wgsl(std::fs::read_to_string("libs/fluid-renderer/src/shader.wgsl").unwrap().into());
     let vertices = Quad.scale(particle_size);
     let indices = Quad::INDICES;
     
-    let mut simulation = Simulation::from_file(simulation_path).unwrap();
+    let mut simulation = Simulation::from_file(simulation_path.clone()).unwrap();
+    let mut simulation_path = simulation_path;
     
-    let camera = Camera {
-        aspect: aspect_ratio,
-        fovy: 45.0,
-        eye: vec3a(-200.0, 200.0, 1000.0) / 4.0,
-        zfar: 10000.0,
-        ..Default::default()
-    };
+    let camera = simulation_camera(&simulation, aspect_ratio);
 
-    let instances = (0..simulation.particle_num).map(|_id| Instance::new()).collect();
+    let instances = (0..simulation.instance_num()).map(|_id| Instance::new()).collect();
 
     let mut state = pollster::block_on(
         State::new(
@@ -88,7 +125,14 @@ pub fn run_simulation(simulation_path: String, fps: u32, particle_size: f32) {
     let (mut imgui_ctxt, mut imgui_platform, mut imgui_renderer) = init_ui(&state, 8.0);
     let mut frame_delta = Duration::from_millis(0);
 
-    let mut is_playing = false;
+    let mut playback = Playback::new(speed, interpolation, mode);
+    let mut interpolation = INTERPOLATIONS.iter().position(|method| *method == interpolation).unwrap_or(0);
+    let mut mode = PLAYBACK_MODES.iter().position(|playback_mode| *playback_mode == mode).unwrap_or(0);
+    let mut bookmark_name = String::new();
+    // result of the last bookmark save shown under the button
+    let mut save_status = String::new();
+    let mut last_redraw = Instant::now();
+    let mut color_channel = 0;
     let files = load_files(std::env::current_dir().unwrap())
         .into_iter()
         .map(|path| path.path().to_str().unwrap().to_string())
@@ -108,8 +152,11 @@ pub fn run_simulation(simulation_path: String, fps: u32, particle_size: f32) {
             Event::RedrawRequested(window_id) if window_id == state.window().id() => {
                 state.update();
 
-                if is_playing && simulation.frame_index < simulation.frame_stop as usize {
-                    simulation.step_forward(&mut state.instances, 1);
+                let elapsed = last_redraw.elapsed();
+                last_redraw = Instant::now();
+                if playback.is_playing {
+                    playback.advance(&simulation, elapsed);
+                    simulation.update_instances_at(&mut state.instances, playback.time, playback.interpolation);
                     state.update_instances();
                 }
 
@@ -117,53 +164,148 @@ pub fn run_simulation(simulation_path: String, fps: u32, particle_size: f32) {
                 imgui_ctxt.io_mut().update_delta_time(frame_delta);
                 let ui = imgui_ctxt.frame();
 
+                let mut redraw = false;
                 {
                     ui.window("Info")
                         .build(|| {
-                            ui.text(format!("Snimek: {}", simulation.frame_index.min(simulation.frame_stop as usize)));
-                            let play_button_text = if is_playing {
+                            ui.text(format!("Snimek: {}, {:.3}s", simulation.frame_index.min(simulation.frame_stop as usize), playback.time));
+                            let play_button_text = if playback.is_playing {
                                 "||"
                             } else {
                                 " >"
                             };
 
-                            if files.len() > 0 {
+                            if !files.is_empty() {
                                 ui.menu("Soubor animace", || {
                                     for file in files.iter() {
                                         if ui.menu_item(file) {
                                             simulation = Simulation::from_file(file.clone()).unwrap();
-                                            state.update_instances();
+                                            simulation_path = file.clone();
+                                            save_status.clear();
+                                            color_channel = 0;
+                                            playback.seek(&simulation, 0.0);
+                                            state.camera = simulation_camera(&simulation, aspect_ratio);
+                                            state.resize_instances((0..simulation.instance_num()).map(|_id| Instance::new()).collect());
+                                            redraw = true;
                                         }
                                     }
                                 });
                             };
 
                             ui.group(|| {
-                                if ui.button("<<") {
-                                    simulation.step_back(&mut state.instances, 5);
-                                    state.update_instances();
+                                for (label, step) in [("<<", 5), ("<", 1)] {
+                                    if ui.button(label) {
+                                        simulation.step_back(&mut state.instances, step);
+                                        playback.seek_frame(&simulation, simulation.frame_index as u32);
+                                        redraw = true;
+                                    }
+                                    ui.same_line();
                                 }
 
-                                ui.same_line();
                                 if ui.button(play_button_text) {
-                                    is_playing = !is_playing;
+                                    playback.is_playing = !playback.is_playing;
+                                    // playing from the end of recording starts again
+                                    if playback.speed > 0.0 && playback.time >= simulation.last_frame_time() {
+                                        playback.seek(&simulation, 0.0);
+                                    } else if playback.speed < 0.0 && playback.time <= 0.0 {
+                                        playback.seek(&simulation, simulation.last_frame_time());
+                                    }
                                 }
 
-                                ui.same_line();
-                                if ui.button(">>") {
-                                    simulation.step_forward(&mut state.instances, 5);
-                                    state.update_instances();
+                                for (label, step) in [(">", 1), (">>", 5)] {
+                                    ui.same_line();
+                                    if ui.button(label) {
+                                        simulation.step_forward(&mut state.instances, step);
+                                        playback.seek_frame(&simulation, simulation.frame_index as u32);
+                                        redraw = true;
+                                    }
                                 }
                             });
 
+                            let mut time = playback.time;
+                            if ui.slider("Cas (s)", 0.0, simulation.last_frame_time(), &mut time) {
+                                playback.seek(&simulation, time);
+                                redraw = true;
+                            }
+                            let mut frame = simulation.frame_index as i32;
+                            if ui.input_int("Prejit na snimek", &mut frame).build() {
+                                playback.seek_frame(&simulation, frame.max(0) as u32);
+                                redraw = true;
+                            }
+
+                            ui.slider("Rychlost", -4.0, 4.0, &mut playback.speed);
+                            for (label, speed) in [("0.1x", 0.1), ("1x", 1.0), ("2x", 2.0), ("-1x", -1.0)] {
+                                if ui.button(label) {
+                                    playback.speed = speed;
+                                }
+                                ui.same_line();
+                            }
+                            ui.new_line();
+                            if ui.combo_simple_string("Na konci", &mut mode, &["Zastavit", "Smycka", "Tam a zpet"]) {
+                                playback.mode = PLAYBACK_MODES[mode];
+                            }
+                            if ui.combo_simple_string("Interpolace", &mut interpolation, &["Zadna", "Linearni", "Hermite"]) {
+                                playback.interpolation = INTERPOLATIONS[interpolation];
+                                redraw = true;
+                            }
+
+                            if !simulation.channels.is_empty() {
+                                let mut names = vec!["Puvodni".to_string()];
+                                names.extend(simulation.channels.iter().map(|channel| channel.name.clone()));
+                                if ui.combo_simple_string("Barva", &mut color_channel, &names) {
+                                    simulation.set_color_channel(color_channel.checked_sub(1));
+                                    redraw = true;
+                                }
+                            }
+
                             if ui.button("Replay") {
-                                simulation.frame_index = 0;
-                                simulation.update_instances(&mut state.instances);
-                                state.update_instances();
+                                playback.seek(&simulation, 0.0);
+                                redraw = true;
+                            }
+
+                            ui.separator();
+                            ui.input_text("Nazev", &mut bookmark_name).build();
+                            ui.same_line();
+                            if ui.button("Pridat zalozku") && !bookmark_name.is_empty() {
+                                simulation.add_bookmark(&bookmark_name, simulation.frame_index as u32);
+                                bookmark_name.clear();
+                            }
+
+                            let mut removed = None;
+                            for (index, bookmark) in simulation.bookmarks.iter().enumerate() {
+                                if ui.button(format!("{} ({})##bookmark{}", bookmark.name, bookmark.frame, index)) {
+                                    playback.seek_frame(&simulation, bookmark.frame);
+                                    redraw = true;
+                                }
+                                ui.same_line();
+                                if ui.button(format!("x##bookmark{}", index)) {
+                                    removed = Some(index);
+                                }
+                            }
+                            if let Some(index) = removed {
+                                simulation.bookmarks.remove(index);
+                            }
+
+                            if ui.button("Ulozit zalozky") {
+                                save_status = match simulation.save(simulation_path.clone()) {
+                                    Ok(()) => "Zalozky ulozeny".to_string(),
+                                    Err(err) => {
+                                        eprintln!("Error: {}", err);
+                                        format!("Chyba ukladani: {}", err)
+                                    }
+                                };
+                            }
+                            if !save_status.is_empty() {
+                                ui.text(&save_status);
                             }
                         });
                 }
 
+                if redraw {
+                    simulation.update_instances_at(&mut state.instances, playback.time, playback.interpolation);
+                    state.update_instances();
+                }
+
                 fluid_renderer::handle_rendering(&mut state, &mut imgui_renderer, imgui_ctxt.render(), control_flow);
                 sleep(Duration::from_millis((1000.0 / fps as f32 - frame_delta.as_millis() as f32) as u64));
             }
@@ -178,23 +320,127 @@ pub fn run_simulation(simulation_path: String, fps: u32, particle_size: f32) {
 }
 
 
+/// Create default perspective camera looking at the domain from the side
+///
+/// # Arguments
+/// * `aspect` - aspect ratio of the window
+fn perspective_camera(aspect: f32) -> Camera {
+    Camera {
+        aspect,
+        fovy: 45.0,
+        eye: vec3a(-200.0, 200.0, 1000.0) / 4.0,
+        zfar: 10000.0,
+        ..Default::default()
+    }
+}
+
+/// Create camera looking along -z axis at the box between min and max, the camera is placed far away
+/// with narrow field of view, so the perspective distortion is negligible
+///
+/// # Arguments
+/// * `aspect` - aspect ratio of the window
+/// * `min` - minimal corner of the viewed box
+/// * `max` - maximal corner of the viewed box
+pub fn orthographic_camera(aspect: f32, min: Vec3A, max: Vec3A) -> Camera {
+    let distance = 10000.0;
+    let target = 0.5 * (min + max);
+    let height = 1.1 * (max.y - min.y).max((max.x - min.x) / aspect).max(1.0);
+
+    Camera {
+        aspect,
+        fovy: 2.0 * (0.5 * height / distance).atan().to_degrees(),
+        eye: target + vec3a(0.0, 0.0, distance),
+        target,
+        znear: 0.5 * distance,
+        zfar: 2.0 * distance,
+        ..Default::default()
+    }
+}
+
+/// Get camera for replaying simulation, 2D simulations are viewed orthographically from the top
+///
+/// # Arguments
+/// * `simulation` - replayed simulation
+/// * `aspect` - aspect ratio of the window
+fn simulation_camera(simulation: &Simulation, aspect: f32) -> Camera {
+    if simulation.dimensions == 2 {
+        let (min, max) = simulation.bounds();
+        orthographic_camera(aspect, min, max)
+    } else {
+        perspective_camera(aspect)
+    }
+}
+
+/// Create square of particles in the xy plane
+///
+/// # Arguments
+/// * `count` - number of particles along x and y axis
+/// * `spacing` - distance between neighboring particles
+/// * `start` - position of the first particle
+///
+/// # Returns
+/// instances of the particles
+pub fn create_square(count: (u32, u32), spacing: f32, start: Vec3A) -> Vec<Instance> {
+    (0..count.1)
+        .flat_map(|y| (0..count.0).map(move |x| (x, y)))
+        .map(|(x, y)| Instance {
+            position: (start + spacing * vec3a(x as f32, y as f32, 0.0)).to_array(),
+            color: [0.1, 0.4, 0.9],
+        })
+        .collect()
+}
+
+
+/// Vorticity models in order of the settings combo box
+const VORTICITY_MODELS: [VorticityModel; 3] = [VorticityModel::None, VorticityModel::Confinement, VorticityModel::Micropolar];
+
+/// Guard policies in order of the settings combo box
+const GUARD_POLICIES: [GuardPolicy; 2] = [GuardPolicy::Abort, GuardPolicy::Rollback];
+
+/// Playback modes in order of the player combo box
+const PLAYBACK_MODES: [PlaybackMode; 3] = [PlaybackMode::Once, PlaybackMode::Loop, PlaybackMode::PingPong];
+
+/// Interpolations in order of the player combo box
+const INTERPOLATIONS: [Interpolation; 3] = [Interpolation::None, Interpolation::Linear, Interpolation::Hermite];
+
+/// Integrators in order of the settings combo box
+const INTEGRATORS: [Integrator; 5] = [
+    Integrator::SymplecticEuler,
+    Integrator::Leapfrog,
+    Integrator::VelocityVerlet,
+    Integrator::RungeKutta2,
+    Integrator::RungeKutta4,
+];
+
+/// Create viscosity model in order of the settings combo box, the parameters are preset from the viscosity slider
+///
+/// # Arguments
+/// * `index` - index of the model in the combo box
+/// * `viscosity` - viscosity set by user, used as the lowest viscosity of the model
+fn viscosity_model(index: usize, viscosity: Real) -> ViscosityModel {
+    match index {
+        1 => ViscosityModel::PowerLaw { consistency: viscosity, index: 0.5 },
+        2 => ViscosityModel::Cross { viscosity_0: 10.0 * viscosity, viscosity_inf: viscosity, time_constant: 1.0, index: 1.0 },
+        3 => ViscosityModel::Carreau { viscosity_0: 10.0 * viscosity, viscosity_inf: viscosity, time_constant: 1.0, index: 0.5 },
+        4 => ViscosityModel::Bingham { plastic_viscosity: viscosity, yield_stress: viscosity, max_viscosity: 10.0 * viscosity },
+        _ => ViscosityModel::Newtonian,
+    }
+}
+
 /// Start application in simulation(default) mode
 pub fn compute_simulation(
     path: String, 
     fps: u32, 
-    simulation_time: u32,
-    fluid_step_time: f32, 
-    instances: Vec<Instance>, 
-    particle_size: f32,
-    particle_offset: f32,
+    simulation_time: f32,
+    fluid_step_time: Real, 
+    scene: Scene, 
+    particle_size: Real,
+    particle_offset: Real,
+    rigid_bodies: Vec<RigidBody>,
 ) {
-    let config = Config::from_instances( 
-        vec3a(-60.0, -40.0, -60.0),
-        vec3a(60.0, 40.0, 60.0),
-        particle_size,
-        1000.0,
-        &instances
-    );
+    let instances = scene.instances(particle_size, particle_offset);
+    let mut config = scene.config(&instances, particle_size, 1000.0);
+    config.rigid_bodies.extend(rigid_bodies.iter().cloned());
     let mut fluid = WCSPHSolver::new(
         0.01,
         50000.0,
@@ -204,25 +450,30 @@ pub fn compute_simulation(
     );
 
     let mut simulation_time = simulation_time;
-    let mut frame_stop = (simulation_time * fps) as u32;
-    let steps_per_frame = (1.0 / fluid_step_time / fps as f32).ceil() as u32;
+    let mut frame_stop = (simulation_time * fps as f32).round() as u32;
+    let steps_per_frame = (1.0 / fluid_step_time / fps as Real).ceil() as u32;
     
     let mut simulation = Simulation::new(fps, frame_stop, instances.len() as u32);
+    let mut diffuse = DiffuseGenerator::new(DiffuseSettings::default());
+    let mut diffuse_recording = DiffuseRecording::new(fps);
 
     let InitOutput{event_loop, window, aspect_ratio} = init(); 
     let shader_source = fluid_renderer::wgpu::ShaderSource::Wgsl(std::fs::read_to_string("libs/fluid-renderer/src/shader.wgsl").unwrap().into());
-    let vertices = Quad.scale(particle_size);
+    let vertices = Quad.scale(real_to_single(particle_size));
     let indices = Quad::INDICES;
     
-    let camera = Camera {
-        aspect: aspect_ratio,
-        fovy: 45.0,
-        eye: vec3a(-200.0, 200.0, 1000.0) / 4.0,
-        zfar: 10000.0,
-        ..Default::default()
+    let (domain_start, domain_end) = scene.domain();
+    let camera = if scene.dimensions() == 2 {
+        orthographic_camera(aspect_ratio, to_single(domain_start), to_single(domain_end))
+    } else {
+        perspective_camera(aspect_ratio)
     };
 
-    let default_instances = instances.clone();
+    let default_instances = if scene.dimensions() == 2 {
+        Scene::DamBreak.instances(particle_size, particle_offset)
+    } else {
+        instances.clone()
+    };
 
     let mut state = pollster::block_on(
         State::new(
@@ -244,17 +495,35 @@ pub fn compute_simulation(
 
     let mut frame = 0;
 
-    let mut viscosity = 0.01;
-    let mut stiffness = 50000.0;
+    let mut viscosity: Real = 0.01;
+    let mut implicit_viscosity = false;
+    let mut viscosity_model_index = 0;
+    let mut stiffness = 2.0e7;
     let mut surface_tension = 0.01;
+    let mut akinci_surface_tension = false;
+    let mut adhesion = 0.0;
+    let mut vorticity_model = 0;
+    let mut integrator = 0;
+    let mut vorticity_strength = 0.1;
+    let mut record_vorticity = false;
+    let mut heated_floor = false;
+    let mut floor_temperature = 80.0;
+    let mut generate_diffuse = false;
+    let mut sand: Option<GranularSolver> = None;
+    let mut granular = scene.is_granular();
+    let mut friction_angle: Real = 30.0;
+    let mut cohesion = 0.0;
+    let mut two_dimensional = scene.dimensions() == 2;
+    let mut record_diagnostics = true;
+    let mut diagnostics_log = DiagnosticsLog::new();
+    let mut guard_policy = 0;
+    let mut guard_report: Option<GuardReport> = None;
+    let mut save_status = String::new();
 
     let mut particle_size = particle_size;
     let mut particle_offset = particle_offset;
     let particle_count = 14;
 
-    let domain_start = vec3a(-60.0, -40.0, -60.0);
-    let domain_end = vec3a(60.0, 40.0, 60.0);
-
     let mut rest_density = 1000.0;
 
 
@@ -284,18 +553,70 @@ pub fn compute_simulation(
 
                         println!("Hotovo, {}s", total_time.elapsed().as_millis() as f32 / 1000.0);
                         simulation.save((&path).clone()).unwrap();
+                        if generate_diffuse {
+                            let diffuse_path = PathBuf::from(&path).with_extension("diffuse");
+                            if let Err(err) = diffuse_recording.save(diffuse_path.to_str().unwrap().to_string()) {
+                                eprintln!("Error: {}", err);
+                                save_status = format!("Chyba ukladani peny: {}", err);
+                            }
+                        }
+                        if record_diagnostics {
+                            let diagnostics_path = PathBuf::from(&path).with_extension("csv");
+                            diagnostics_log.save(diagnostics_path.to_str().unwrap().to_string()).unwrap();
+                        }
                     }
 
+                    let guard = Guard::new(GUARD_POLICIES[guard_policy]);
                     for _step in 0..steps_per_frame {
-                        fluid.step();
+                        let result = match sand.as_mut() {
+                            Some(sand) => guard.step(sand),
+                            None => guard.step(&mut fluid),
+                        };
+                        if let Err(report) = result {
+                            println!("Simulace zastavena: {}", report);
+                            guard_report = Some(report);
+                            break;
+                        }
+
+                        if record_diagnostics {
+                            let solver: &dyn Solver = match sand.as_ref() {
+                                Some(sand) => sand,
+                                None => &fluid,
+                            };
+                            diagnostics_log.push(Diagnostics::compute(solver, fluid_step_time, WCSPHSolver::G));
+                        }
+                    }
+                    if guard_report.is_some() {
+                        is_playing = false;
+                        frame = 0;
                     }
+                }
 
-                    for (particle_id, instance_id) in fluid.ps().ids.iter().enumerate() {
-                        let index = frame as usize * state.instances.len() + *instance_id;
-                        simulation.frames[index] = fluid.ps().x[particle_id];
+                if is_playing {
+                    let solver: &dyn Solver = match sand.as_ref() {
+                        Some(sand) => sand,
+                        None => &fluid,
+                    };
+                    simulation.record_frame(frame as usize, solver.ps());
+                    solver.ps().advect_instances(&mut state.instances);
+
+                    if sand.is_none() {
+                        if record_vorticity {
+                            if fluid.vorticity_model == VorticityModel::None {
+                                fluid.compute_vorticity();
+                            }
+                            let magnitudes = fluid.vorticity.iter().map(|vorticity| vorticity.length()).collect::<Vec<Real>>();
+                            simulation.record_channel("vorticity", frame as usize, &fluid.ps().ids, &magnitudes);
+                        }
+                        if fluid.thermal.is_some() {
+                            simulation.record_channel("temperature", frame as usize, &fluid.ps().ids, &fluid.ps().temperature);
+                        }
+                        if generate_diffuse {
+                            diffuse.update(&fluid, steps_per_frame as Real * fluid_step_time, WCSPHSolver::G);
+                            diffuse_recording.record(frame as usize, &diffuse.particles);
+                        }
                     }
 
-                    fluid.advect_instances(&mut state.instances);
                     state.update_instances();
                     println!("progress: {}/{} {}%, {}s", frame, frame_stop, frame*100/frame_stop, frame_start.elapsed().as_millis() as f32 / 1000.0);
 
@@ -308,14 +629,45 @@ pub fn compute_simulation(
                         .position([5.0, 5.0], imgui::Condition::FirstUseEver)
                         .size([180.0, 240.0], imgui::Condition::FirstUseEver)
                         .build(|| {
-                            ui.slider("Viskozita", 0.01, 1.5, &mut viscosity);
-                            ui.slider("Tuhost", 1000.0, 300_000.0, &mut stiffness);
+                            let max_viscosity = if implicit_viscosity { 1000.0 } else { 1.5 };
+                            ui.slider_config("Viskozita", 0.01, max_viscosity)
+                                .flags(imgui::SliderFlags::LOGARITHMIC)
+                                .build(&mut viscosity);
+                            if ui.checkbox("Implicitni viskozita", &mut implicit_viscosity) {
+                                viscosity = viscosity.min(max_viscosity);
+                            }
+                            ui.combo_simple_string("Model viskozity", &mut viscosity_model_index, &["Newtonovsky", "Mocninny", "Cross", "Carreau", "Bingham"]);
+                            ui.slider("Tuhost", 1.0e6, 1.0e8, &mut stiffness);
                             ui.slider("Povrch. napeti", 0.01, 4.0, &mut surface_tension);
+                            ui.checkbox("Model Akinci", &mut akinci_surface_tension);
+                            ui.slider("Adheze", 0.0, 4.0, &mut adhesion);
+                            ui.combo_simple_string("Integrator", &mut integrator, &["Symplekticky Euler", "Leapfrog", "Velocity Verlet", "RK2", "RK4"]);
+                            ui.combo_simple_string("Vorticita model", &mut vorticity_model, &["Zadny", "Confinement", "Mikropolarni"]);
+                            ui.slider("Vorticita", 0.0, 1.0, &mut vorticity_strength);
+                            ui.checkbox("Zaznam vorticity", &mut record_vorticity);
+                            ui.checkbox("Ohrev dna", &mut heated_floor);
+                            ui.checkbox("Pena a sprej", &mut generate_diffuse);
+                            ui.checkbox("Pisek", &mut granular);
+                            ui.checkbox("2D", &mut two_dimensional);
+                            ui.checkbox("Diagnostika", &mut record_diagnostics);
+                            ui.combo_simple_string("Pri explozi", &mut guard_policy, &["Zastavit", "Vratit krok"]);
+                            if granular {
+                                ui.slider("Uhel treni", 10.0, 45.0, &mut friction_angle);
+                                ui.slider("Koheze", 0.0, 1000.0, &mut cohesion);
+                            }
+                            if heated_floor {
+                                ui.slider("Teplota dna", 20.0, 100.0, &mut floor_temperature);
+                            }
                             ui.slider("Hustota", 500.0, 5000.0, &mut rest_density);
-                            if ui.slider("Delka sim. (s)", 1, 60, &mut simulation_time) {
-                                frame_stop = (simulation_time * fps) as u32;
+                            if ui.slider("Delka sim. (s)", 1.0, 60.0, &mut simulation_time) {
+                                frame_stop = (simulation_time * fps as f32).round() as u32;
                                 simulation.frame_stop = frame_stop;
                                 simulation.frames = (0..(simulation.particle_num * frame_stop)).map(|_id| Vec3A::ZERO).collect();
+                                simulation.rigid_bodies.clear();
+                                simulation.channels.clear();
+                                diffuse = DiffuseGenerator::new(DiffuseSettings::default());
+                                diffuse_recording = DiffuseRecording::new(fps);
+                                diagnostics_log = DiagnosticsLog::new();
                             }
                             ui.separator();
 
@@ -332,12 +684,9 @@ pub fn compute_simulation(
                                 //     let instances = create_cube(0.04, (particle_count, particle_count, particle_count), Some(particle_offset), (-1.0, -1.0, -1.0));
                                 //     state.resize_instances(instances);
                                 // }
-                                if ui.slider("Mezera", 0.1, 2.0, &mut particle_offset) {
-                                    let particle_offset = (
-                                        particle_size * particle_offset,
-                                        particle_size * particle_offset,
-                                        particle_size * particle_offset,
-                                    );
+                                if ui.slider("Mezera", 0.1, 4.0, &mut particle_offset) {
+                                    let spacing = real_to_single(particle_size * particle_offset);
+                                    let particle_offset = (spacing, spacing, spacing);
 
                                     state.instances = create_cube(0.04, (particle_count, particle_count, particle_count), Some(particle_offset), (-1.0, -1.0, -1.0));
                                     state.update_instances();
@@ -346,6 +695,19 @@ pub fn compute_simulation(
                             ui.separator();
 
                             ui.text(format!("Snimek: {}", frame.min(simulation.frame_stop)));
+                            if let Some(report) = guard_report.as_ref() {
+                                ui.text_wrapped(format!("Chyba: {}", report));
+                            }
+                            if !save_status.is_empty() {
+                                ui.text_wrapped(&save_status);
+                            }
+                            if let Some(diagnostics) = diagnostics_log.last() {
+                                ui.text(format!("Energie: {:.3e} (drift {:.2}%)", diagnostics.total_energy(), 100.0 * diagnostics_log.energy_drift(diagnostics)));
+                                ui.text(format!("Hybnost: [{:.2}, {:.2}, {:.2}]", diagnostics.momentum.x, diagnostics.momentum.y, diagnostics.momentum.z));
+                                ui.text(format!("Chyba hustoty: {:.2}% (max {:.2}%)", 100.0 * diagnostics.average_density_error, 100.0 * diagnostics.max_density_error));
+                                ui.text(format!("Max rychlost: {:.2}, CFL: {:.3}", diagnostics.max_velocity, diagnostics.cfl));
+                                ui.text(format!("Sousede: {} / {:.1} / {}", diagnostics.min_neighbors, diagnostics.average_neighbors, diagnostics.max_neighbors));
+                            }
                             // ui.slider("", min, max, value);
 
                             if is_playing {
@@ -354,18 +716,37 @@ pub fn compute_simulation(
                             }
 
                             ui.spacing();
+                            // toggling 2D replaces the scene by dam break of the other dimension
+                            let active_scene = if two_dimensional == (scene.dimensions() == 2) {
+                                scene
+                            } else if two_dimensional {
+                                Scene::DamBreak2d
+                            } else {
+                                Scene::DamBreak
+                            };
                             if frame == 1 {
                                 if ui.button("Restart") {
-                                    state.instances = default_instances.clone();
-                                    state.update_instances();
+                                    let instances = if two_dimensional {
+                                        active_scene.instances(particle_size, particle_offset)
+                                    } else {
+                                        default_instances.clone()
+                                    };
+                                    state.resize_instances(instances);
+                                    state.camera = if two_dimensional {
+                                        orthographic_camera(aspect_ratio, to_single(domain_start), to_single(domain_end))
+                                    } else {
+                                        perspective_camera(aspect_ratio)
+                                    };
+                                    if simulation.particle_num as usize != state.instances.len() {
+                                        simulation = Simulation::new(fps, frame_stop, state.instances.len() as u32);
+                                    }
 
-                                    let config = Config::from_instances( 
-                                        domain_start,
-                                        domain_end,
-                                        particle_size,
-                                        rest_density,
-                                        &state.instances
-                                    );
+                                    let mut config = active_scene.config(&state.instances, particle_size, rest_density);
+                                    config.rigid_bodies.extend(rigid_bodies.iter().cloned());
+                                    if heated_floor {
+                                        let floor_end = vector(domain_end.x, domain_start.y + 4.0 * particle_size, domain_end.z);
+                                        config.thermal_regions.push(ThermalRegion::new(domain_start, floor_end, floor_temperature, 5.0));
+                                    }
 
                                     fluid = WCSPHSolver::new(
                                         viscosity,
@@ -374,6 +755,37 @@ pub fn compute_simulation(
                                         fluid_step_time,
                                         config
                                     );
+                                    fluid.surface_tension_model = if akinci_surface_tension {
+                                        SurfaceTensionModel::Akinci
+                                    } else {
+                                        SurfaceTensionModel::Simple
+                                    };
+                                    fluid.adhesion = adhesion;
+                                    fluid.viscosity_method = if implicit_viscosity {
+                                        ViscosityMethod::Implicit
+                                    } else {
+                                        ViscosityMethod::Explicit
+                                    };
+                                    fluid.viscosity_model = viscosity_model(viscosity_model_index, viscosity);
+                                    fluid.vorticity_model = VORTICITY_MODELS[vorticity_model];
+                                    fluid.vorticity_strength = vorticity_strength;
+                                    fluid.integrator = INTEGRATORS[integrator];
+                                    if heated_floor {
+                                        fluid.thermal = Some(Thermal::new(1.0, 0.003, 20.0));
+                                    }
+                                    sand = if granular {
+                                        let mut config = Config::from_instances(domain_start, domain_end, particle_size, rest_density, &state.instances);
+                                        config.dimensions = if two_dimensional { 2 } else { 3 };
+                                        Some(GranularSolver::new(friction_angle.to_radians(), cohesion, fluid_step_time, config))
+                                    } else {
+                                        None
+                                    };
+                                    simulation.channels.clear();
+                                    diffuse = DiffuseGenerator::new(DiffuseSettings::default());
+                                    diffuse_recording = DiffuseRecording::new(fps);
+                                    diagnostics_log = DiagnosticsLog::new();
+                                    guard_report = None;
+                                    save_status.clear();
                                     
                                     frame = 0;
                                 }
@@ -382,13 +794,22 @@ pub fn compute_simulation(
                             }
 
                             if ui.button("Start") {
-                                let config = Config::from_instances( 
-                                    domain_start,
-                                    domain_end,
-                                    particle_size,
-                                    rest_density,
-                                    &state.instances
-                                );
+                                if two_dimensional {
+                                    state.resize_instances(active_scene.instances(particle_size, particle_offset));
+                                    state.camera = orthographic_camera(aspect_ratio, to_single(domain_start), to_single(domain_end));
+                                    simulation = Simulation::new(fps, frame_stop, state.instances.len() as u32);
+                                } else if state.instances.len() != default_instances.len() {
+                                    state.resize_instances(default_instances.clone());
+                                    state.camera = perspective_camera(aspect_ratio);
+                                    simulation = Simulation::new(fps, frame_stop, state.instances.len() as u32);
+                                }
+
+                                let mut config = active_scene.config(&state.instances, particle_size, rest_density);
+                                config.rigid_bodies.extend(rigid_bodies.iter().cloned());
+                                if heated_floor {
+                                    let floor_end = vector(domain_end.x, domain_start.y + 4.0 * particle_size, domain_end.z);
+                                    config.thermal_regions.push(ThermalRegion::new(domain_start, floor_end, floor_temperature, 5.0));
+                                }
 
                                 fluid = WCSPHSolver::new(
                                     viscosity,
@@ -397,6 +818,37 @@ pub fn compute_simulation(
                                     fluid_step_time,
                                     config
                                 );
+                                fluid.surface_tension_model = if akinci_surface_tension {
+                                    SurfaceTensionModel::Akinci
+                                } else {
+                                    SurfaceTensionModel::Simple
+                                };
+                                fluid.adhesion = adhesion;
+                                fluid.viscosity_method = if implicit_viscosity {
+                                    ViscosityMethod::Implicit
+                                } else {
+                                    ViscosityMethod::Explicit
+                                };
+                                fluid.viscosity_model = viscosity_model(viscosity_model_index, viscosity);
+                                fluid.vorticity_model = VORTICITY_MODELS[vorticity_model];
+                                fluid.vorticity_strength = vorticity_strength;
+                                fluid.integrator = INTEGRATORS[integrator];
+                                if heated_floor {
+                                    fluid.thermal = Some(Thermal::new(1.0, 0.003, 20.0));
+                                }
+                                sand = if granular {
+                                    let mut config = Config::from_instances(domain_start, domain_end, particle_size, rest_density, &state.instances);
+                                    config.dimensions = if two_dimensional { 2 } else { 3 };
+                                    Some(GranularSolver::new(friction_angle.to_radians(), cohesion, fluid_step_time, config))
+                                } else {
+                                    None
+                                };
+                                simulation.channels.clear();
+                                diffuse = DiffuseGenerator::new(DiffuseSettings::default());
+                                diffuse_recording = DiffuseRecording::new(fps);
+                                diagnostics_log = DiagnosticsLog::new();
+                                guard_report = None;
+                                save_status.clear();
 
                                 is_playing = true;
                                 println!("Starting simulation");
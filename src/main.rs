@@ -1,44 +1,224 @@
-use std::env;
+use std::error::Error;
+use std::process::ExitCode;
 
-use fluid_renderer::create_cube;
-use nikola::{run_simulation, compute_simulation};
+use clap::{Args, Parser, Subcommand};
+use glam::Vec3A;
+use nikola::{
+    run_simulation, compute_simulation, simulate_headless, export_simulation, ExportFormat, Inspection, Interpolation, PlaybackMode, Real, Scene,
+    Simulation,
+};
 
 
 
 const INSTANCE_PARTICLE_SIZE: f32 = 2.0;
-const SIMULATION_PARTICLE_SIZE: f32 = 2.0;
+const SIMULATION_PARTICLE_SIZE: Real = 2.0;
+const SIMULATION_PARTICLE_OFFSET: Real = 2.0;
 
 const SIMULATION_PATH: &str = "./simulation.nk";
 const FPS: u32 = 60;
+const DURATION: f32 = 10.0;
 
-const FLUID_STEP_TIME: f32 = 0.004;
+const FLUID_STEP_TIME: Real = 0.004;
 
-fn main() {
-    let particle_offset = (
-       SIMULATION_PARTICLE_SIZE * 1.0, 
-       SIMULATION_PARTICLE_SIZE * 1.0, 
-       SIMULATION_PARTICLE_SIZE * 1.0, 
-    );
-    let instances = create_cube(0.04, (14, 14, 14), Some(particle_offset), (-1.0, -1.0, -1.0));
 
-    let mut args = env::args().collect::<Vec<String>>();
+/// SPH simulation of fluids and sand, without command the dam break is simulated in window
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Simulate scene and save the recording, diagnostics are saved next to it as CSV
+    Simulate {
+        /// Simulated scene
+        #[arg(value_enum, default_value_t = Scene::DamBreak)]
+        scene: Scene,
+        /// Open window with settings and preview instead of simulating in terminal
+        #[arg(long)]
+        preview: bool,
+        #[command(flatten)]
+        settings: SimulationSettings,
+    },
+    /// Replay recorded simulation in window
+    #[command(alias = "run")]
+    Play {
+        /// Recorded simulation
+        #[arg(default_value = SIMULATION_PATH)]
+        file: String,
+        /// Maximal number of drawn frames per second
+        #[arg(long, default_value_t = FPS)]
+        fps: u32,
+        /// Speed of the replay relative to real time, negative speed plays backwards
+        #[arg(long, default_value_t = 1.0, allow_negative_numbers = true)]
+        speed: f32,
+        /// Computation of positions between recorded frames
+        #[arg(long, value_enum, default_value_t = Interpolation::Hermite)]
+        interpolation: Interpolation,
+        /// Behaviour at the ends of recording
+        #[arg(long, value_enum, default_value_t = PlaybackMode::Once)]
+        mode: PlaybackMode,
+    },
+    /// Print header and metadata of recorded simulation
+    Info {
+        /// Recorded simulation
+        file: String,
+    },
+    /// Print statistics of recorded simulation, fails when positions are NaN or outside of the domain
+    Inspect {
+        /// Recorded simulation
+        file: String,
+        /// Domain as minimal and maximal corner, defaults to the domain stored in the recording
+        #[arg(long, num_args = 6, allow_negative_numbers = true, value_names = ["MIN_X", "MIN_Y", "MIN_Z", "MAX_X", "MAX_Y", "MAX_Z"])]
+        domain: Option<Vec<f32>>,
+        /// Save statistics of each frame as CSV
+        #[arg(long)]
+        csv: Option<String>,
+    },
+    /// Export recorded simulation for other tools
+    Convert {
+        /// Recorded simulation
+        input: String,
+        /// Exported file, formats storing single frame get the frame number appended
+        output: String,
+        /// Format of the exported files
+        #[arg(long, value_enum)]
+        format: ExportFormat,
+        /// Frames per second of the export, frames of the recording are skipped or repeated
+        #[arg(long)]
+        fps: Option<u32>,
+        /// Length of the export (s)
+        #[arg(long)]
+        duration: Option<f32>,
+    },
+    /// Check that recorded simulation is complete and consistent
+    Validate {
+        /// Recorded simulation
+        file: String,
+    },
+}
+
+#[derive(Debug, Args)]
+struct SimulationSettings {
+    /// Frames per second of the recording
+    #[arg(long, default_value_t = FPS)]
+    fps: u32,
+    /// Length of the simulation (s)
+    #[arg(long, default_value_t = DURATION)]
+    duration: f32,
+    /// Length of time step (s)
+    #[arg(long, default_value_t = FLUID_STEP_TIME)]
+    dt: Real,
+    /// Path to the recorded simulation
+    #[arg(short, long, default_value = SIMULATION_PATH)]
+    output: String,
+}
 
-    if args.len() == 1 {
-        args.push(String::new());
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
     }
+}
+
+/// Execute command given on command line
+///
+/// # Arguments
+/// * `cli` - parsed arguments
+///
+/// # Returns
+/// exit code or error of the command
+fn run(cli: Cli) -> Result<ExitCode, Box<dyn Error>> {
+    let command = cli.command.unwrap_or(Command::Simulate {
+        scene: Scene::DamBreak,
+        preview: true,
+        settings: SimulationSettings { fps: FPS, duration: DURATION, dt: FLUID_STEP_TIME, output: SIMULATION_PATH.to_string() },
+    });
+
+    match command {
+        Command::Simulate { scene, preview, settings } => {
+            if settings.fps == 0 || settings.duration <= 0.0 || settings.dt <= 0.0 {
+                return Err("fps, duration and dt have to be positive".into());
+            }
 
-    match args[1].as_str() {
-        "run" => {
-            dbg!(&args);
-            let path = if args.len() >= 3 {
-                args[2].clone()
+            if preview {
+                compute_simulation(
+                    settings.output, settings.fps, settings.duration, settings.dt, scene,
+                    SIMULATION_PARTICLE_SIZE, SIMULATION_PARTICLE_OFFSET, Vec::new()
+                )
             } else {
-                SIMULATION_PATH.to_string()
-            };
+                simulate_headless(
+                    settings.output, settings.fps, settings.duration, settings.dt, scene,
+                    SIMULATION_PARTICLE_SIZE, SIMULATION_PARTICLE_OFFSET
+                )?
+            }
+        },
+        Command::Play { file, fps, speed, interpolation, mode } => {
+            println!("Loading: {}", file);
+            run_simulation(file, fps, INSTANCE_PARTICLE_SIZE, speed, interpolation, mode)
+        },
+        Command::Info { file } => {
+            let simulation = Simulation::from_file(file)?;
+            println!("{}", simulation);
+        },
+        Command::Inspect { file, domain, csv } => {
+            let simulation = Simulation::from_file(file)?;
+            println!("{}", simulation);
+            let problems = simulation.validate();
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+
+            let domain = domain
+                .map(|domain| (Vec3A::from_slice(&domain[0..3]), Vec3A::from_slice(&domain[3..6])))
+                .or(simulation.domain)
+                .ok_or("recording doesn't store its domain, set it by --domain")?;
+            let inspection = Inspection::compute(&simulation, domain);
+            println!("{}", inspection);
+            if let Some(csv) = csv {
+                inspection.save(csv)?;
+            }
 
-            println!("Loading: {}", path);
-            run_simulation(path, FPS, INSTANCE_PARTICLE_SIZE)
+            if !problems.is_empty() || !inspection.is_valid() {
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        Command::Convert { input, output, format, fps, duration } => {
+            let simulation = Simulation::from_file(input)?;
+            let problems = simulation.validate();
+            if !problems.is_empty() {
+                return Err(format!("invalid recording: {}", problems.join(", ")).into());
+            }
+
+            let simulation = if fps.is_some() || duration.is_some() {
+                let fps = fps.unwrap_or(simulation.fps);
+                if fps == 0 {
+                    return Err("fps has to be positive".into());
+                }
+                simulation.resample(fps, duration)
+            } else {
+                simulation
+            };
+            let files = export_simulation(&simulation, output, format)?;
+            println!("Written {} files", files);
+        },
+        Command::Validate { file } => {
+            let simulation = Simulation::from_file(file)?;
+            let problems = simulation.validate();
+            for problem in problems.iter() {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+            println!("OK");
         },
-        _ => compute_simulation(SIMULATION_PATH.to_string(), FPS, 10, FLUID_STEP_TIME, instances, SIMULATION_PARTICLE_SIZE, 1.0)
     }
+
+    Ok(ExitCode::SUCCESS)
 }
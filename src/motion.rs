@@ -0,0 +1,82 @@
+use crate::{Real, Rotation, Vector, consts};
+
+
+/// Prescribed motion as a function of time, used by kinematic bodies and moving domain
+#[derive(Debug, Clone)]
+pub enum Motion {
+    /// Sinusoidal translation `amplitude * sin(2 pi frequency t + phase)`, e.g. piston wave maker
+    Oscillation { amplitude: Vector, frequency: Real, phase: Real },
+    /// Rotation with constant angular velocity (rad/s) around axis, e.g. mixer impeller
+    Rotation { axis: Vector, angular_velocity: Real },
+    /// Rotational oscillation `amplitude * sin(2 pi frequency t)` (rad) around axis, e.g. flap
+    Rocking { axis: Vector, amplitude: Real, frequency: Real },
+}
+
+impl Motion {
+    /// Get translation from the initial position at time
+    ///
+    /// # Arguments
+    /// * `time` - simulation time (s)
+    pub fn offset(&self, time: Real) -> Vector {
+        match self {
+            Motion::Oscillation { amplitude, frequency, phase } => {
+                *amplitude * (2.0 * consts::PI * frequency * time + phase).sin()
+            }
+            _ => Vector::ZERO,
+        }
+    }
+
+    /// Get velocity of the translation at time
+    ///
+    /// # Arguments
+    /// * `time` - simulation time (s)
+    pub fn velocity(&self, time: Real) -> Vector {
+        match self {
+            Motion::Oscillation { amplitude, frequency, phase } => {
+                let omega = 2.0 * consts::PI * frequency;
+                *amplitude * omega * (omega * time + phase).cos()
+            }
+            _ => Vector::ZERO,
+        }
+    }
+
+    /// Get rotation from the initial orientation at time
+    ///
+    /// # Arguments
+    /// * `time` - simulation time (s)
+    pub fn rotation(&self, time: Real) -> Rotation {
+        match self {
+            Motion::Rotation { axis, angular_velocity } => {
+                Rotation::from_axis_angle(axis.normalize().into(), angular_velocity * time)
+            }
+            Motion::Rocking { axis, amplitude, frequency } => {
+                let angle = amplitude * (2.0 * consts::PI * frequency * time).sin();
+                Rotation::from_axis_angle(axis.normalize().into(), angle)
+            }
+            _ => Rotation::IDENTITY,
+        }
+    }
+
+    /// Get angular velocity (world space) at time
+    ///
+    /// # Arguments
+    /// * `time` - simulation time (s)
+    pub fn angular_velocity(&self, time: Real) -> Vector {
+        match self {
+            Motion::Rotation { axis, angular_velocity } => axis.normalize() * *angular_velocity,
+            Motion::Rocking { axis, amplitude, frequency } => {
+                let omega = 2.0 * consts::PI * frequency;
+                axis.normalize() * (amplitude * omega * (omega * time).cos())
+            }
+            _ => Vector::ZERO,
+        }
+    }
+
+    /// Get largest translation reached during the motion along each axis
+    pub fn max_offset(&self) -> Vector {
+        match self {
+            Motion::Oscillation { amplitude, .. } => amplitude.abs(),
+            _ => Vector::ZERO,
+        }
+    }
+}
@@ -2,23 +2,46 @@ use std::ops::{AddAssign, SubAssign};
 
 use glam::{Vec3A, IVec3, ivec3};
 
-use crate::Config;
+use fluid_renderer::Instance;
+
+use crate::{Real, Vector, Matrix, to_single, Config, Sink, OutflowBoundary, RigidBody, Motion, Phase, ThermalRegion, Simulation};
 
 
 /// Represents a system of particles
+#[derive(Debug, Clone)]
 pub struct ParticleSystem {
-    pub domain_start: Vec3A,
-    pub domain_end: Vec3A,
-    pub domain_size: Vec3A,
+    pub domain_start: Vector,
+    pub domain_end: Vector,
+    pub domain_size: Vector,
+    pub domain_motion: Option<Motion>, // translation of the whole domain (tank)
+    pub time: Real, // simulation time
 
-    pub particle_radius: f32,
-    pub particle_diameter: f32,
-    pub support_radius: f32,
+    pub particle_radius: Real,
+    pub particle_diameter: Real,
+    pub support_radius: Real,
 
     pub particle_num: usize, // number of particles
+    pub periodic: [bool; 3], // whether the domain wraps around along axis
+    pub dimensions: u32, // 2 - particles move in xy plane, 3 - full space
+
+    // Open boundaries
+    pub sinks: Vec<Sink>,
+    pub outflows: Vec<OutflowBoundary>,
+    pub removed_ids: Vec<usize>, // ids of particles deleted by sinks and outflows
+
+    // Rigid bodies coupled with particles
+    pub rigid_bodies: Vec<RigidBody>,
+
+    // Fluid phases, single phase is used when empty
+    pub phases: Vec<Phase>,
+    pub interface_tension: Real,
+
+    // Heated and cooled regions
+    pub thermal_regions: Vec<ThermalRegion>,
 
     // Grid props
-    grid_size: f32,   // cell size
+    grid_start: Vector, // grid covers domain and its motion
+    grid_size: Vector, // cell size
     grid_dims: IVec3, // dimensions of the grid
     grid_len: usize,
 
@@ -28,53 +51,119 @@ pub struct ParticleSystem {
 
     // particle props
     pub ids: Vec<usize>,
-    pub x: Vec<Vec3A>,
-    pub x_0: Vec<Vec3A>,
-    pub v: Vec<Vec3A>,
-    pub acceleration: Vec<Vec3A>,
-    pub m_v: Vec<f32>,
-    pub m: Vec<f32>,
-    pub density: Vec<f32>,
-    pub pressure: Vec<f32>,
+    pub x: Vec<Vector>,
+    pub x_0: Vec<Vector>,
+    pub v: Vec<Vector>,
+    pub acceleration: Vec<Vector>,
+    pub m_v: Vec<Real>,
+    pub m: Vec<Real>,
+    pub density: Vec<Real>,
+    pub pressure: Vec<Real>,
     pub color: Vec<Vec3A>,
+    pub phase: Vec<usize>,
+    pub omega: Vec<Vector>, // angular velocity
+    pub temperature: Vec<Real>,
+    pub stress: Vec<Matrix>, // Cauchy stress of granular material
     
     // sort buffers
     ids_buffer: Vec<usize>,
-    x_buffer: Vec<Vec3A>,
-    x_0_buffer: Vec<Vec3A>,
-    v_buffer: Vec<Vec3A>,
-    acceleration_buffer: Vec<Vec3A>,
-    m_v_buffer: Vec<f32>,
-    m_buffer: Vec<f32>,
-    density_buffer: Vec<f32>,
-    pressure_buffer: Vec<f32>,
+    x_buffer: Vec<Vector>,
+    x_0_buffer: Vec<Vector>,
+    v_buffer: Vec<Vector>,
+    acceleration_buffer: Vec<Vector>,
+    m_v_buffer: Vec<Real>,
+    m_buffer: Vec<Real>,
+    density_buffer: Vec<Real>,
+    pressure_buffer: Vec<Real>,
     color_buffer: Vec<Vec3A>,
+    phase_buffer: Vec<usize>,
+    omega_buffer: Vec<Vector>,
+    temperature_buffer: Vec<Real>,
+    stress_buffer: Vec<Matrix>,
 }
 
 impl ParticleSystem {
     /// Create new ParticleSystem initialized with config and default values
-    pub fn new(config: Config) -> Self {
+    pub fn new(mut config: Config) -> Self {
         let domain_size = config.domain_end - config.domain_start;
 
+        // 2D particles are projected into the middle plane of the domain
+        if config.dimensions == 2 {
+            let plane = 0.5 * (config.domain_start.z + config.domain_end.z);
+            config.x.iter_mut().for_each(|x| x.z = plane);
+            config.v.iter_mut().for_each(|v| v.z = 0.0);
+            config.periodic[2] = false;
+        }
+
         let particle_diameter = 2.0 * config.particle_radius;
         let support_radius = 4.0 * config.particle_radius;
-        let m_v0 = 0.8 * particle_diameter;
-
-        let grid_dims = (domain_size / support_radius).ceil().as_ivec3();
+        // particles sampled on a lattice with spacing of particle diameter have rest density
+        let m_v0 = particle_diameter.powi(config.dimensions as i32);
+
+        // grid has to cover the whole motion of the domain
+        let mut grid_extent = config.domain_motion.as_ref().map_or(Vector::ZERO, |motion| motion.max_offset());
+        for axis in 0..3 {
+            if config.periodic[axis] {
+                grid_extent[axis] = 0.0;
+            }
+        }
+        let mut grid_start = config.domain_start - grid_extent;
+
+        let mut grid_dims = ((domain_size + 2.0 * grid_extent) / support_radius).ceil().as_ivec3();
+        let mut grid_size = Vector::splat(support_radius);
+        // periodic axes need whole number of cells, so the neighbors across the boundary lie in the
+        // wrapped neighboring cell
+        for axis in 0..3 {
+            if config.periodic[axis] {
+                grid_dims[axis] = ((domain_size[axis] / support_radius).floor() as i32).max(1);
+                grid_size[axis] = domain_size[axis] / grid_dims[axis] as Real;
+            }
+        }
+        // 2D grid has single layer of cells
+        if config.dimensions == 2 {
+            grid_dims.z = 1;
+            grid_size.z = domain_size.z.max(support_radius);
+            grid_start.z = 0.5 * (config.domain_start.z + config.domain_end.z - grid_size.z);
+        }
         let grid_len = (grid_dims.x * grid_dims.y * grid_dims.z) as usize;
 
+        let (m, color) = if config.phases.is_empty() {
+            (vec![m_v0 * config.density_0; config.particle_num], config.color)
+        } else {
+            (
+                config.phase.iter().map(|phase| m_v0 * config.phases[*phase].density_0).collect(),
+                config.phase.iter().map(|phase| config.phases[*phase].color).collect(),
+            )
+        };
+
         ParticleSystem { 
             domain_start: config.domain_start, 
             domain_end: config.domain_end, 
             domain_size, 
+            domain_motion: config.domain_motion,
+            time: 0.0,
 
             particle_radius: config.particle_radius, 
             particle_diameter, 
             support_radius, 
             
             particle_num: config.particle_num,
+            periodic: config.periodic,
+            dimensions: config.dimensions,
+
+            sinks: config.sinks,
+            outflows: config.outflows,
+            removed_ids: Vec::new(),
+
+            rigid_bodies: config.rigid_bodies,
+
+            phases: config.phases,
+            interface_tension: config.interface_tension,
+
+            thermal_regions: config.thermal_regions,
 
-            grid_size: support_radius, 
+            grid_start,
+            grid_size,
             grid_dims,
             grid_len,
 
@@ -86,23 +175,31 @@ impl ParticleSystem {
             x: config.x.clone(), 
             x_0: config.x,
             v: config.v,
-            acceleration: vec![Vec3A::ZERO; config.particle_num],
+            acceleration: vec![Vector::ZERO; config.particle_num],
             m_v: vec![m_v0; config.particle_num],
-            m: vec![m_v0 * config.density_0; config.particle_num],
+            m,
             density: vec![config.density_0; config.particle_num] ,
             pressure: vec![0.0; config.particle_num],
-            color: config.color,
+            color,
+            phase: config.phase,
+            omega: vec![Vector::ZERO; config.particle_num],
+            temperature: config.temperature,
+            stress: vec![Matrix::ZERO; config.particle_num],
             
             ids_buffer: vec![0; config.particle_num],
-            x_buffer: vec![Vec3A::ZERO; config.particle_num],
-            x_0_buffer: vec![Vec3A::ZERO; config.particle_num],
-            v_buffer: vec![Vec3A::ZERO; config.particle_num],
-            acceleration_buffer: vec![Vec3A::ZERO; config.particle_num],
+            x_buffer: vec![Vector::ZERO; config.particle_num],
+            x_0_buffer: vec![Vector::ZERO; config.particle_num],
+            v_buffer: vec![Vector::ZERO; config.particle_num],
+            acceleration_buffer: vec![Vector::ZERO; config.particle_num],
             m_v_buffer: vec![0.0; config.particle_num],
             m_buffer: vec![0.0; config.particle_num],
             density_buffer: vec![0.0; config.particle_num],
             pressure_buffer: vec![0.0; config.particle_num],
-            color_buffer: vec![Vec3A::ZERO; config.particle_num]
+            color_buffer: vec![Vec3A::ZERO; config.particle_num],
+            phase_buffer: vec![0; config.particle_num],
+            omega_buffer: vec![Vector::ZERO; config.particle_num],
+            temperature_buffer: vec![0.0; config.particle_num],
+            stress_buffer: vec![Matrix::ZERO; config.particle_num],
         }
     }
 }
@@ -115,8 +212,15 @@ impl ParticleSystem {
     ///
     /// # Returns 
     /// cell position
-    fn pos_to_index(&self, pos: Vec3A) -> IVec3 {
-        ((pos - self.domain_start) / self.grid_size).floor().as_ivec3()
+    fn pos_to_index(&self, pos: Vector) -> IVec3 {
+        let mut grid_index = ((pos - self.grid_start) / self.grid_size).floor().as_ivec3();
+        for axis in 0..3 {
+            if self.periodic[axis] {
+                grid_index[axis] = grid_index[axis].rem_euclid(self.grid_dims[axis]);
+            }
+        }
+
+        grid_index
     }
 
     /// Transform cell position to particle id 
@@ -137,7 +241,7 @@ impl ParticleSystem {
     ///
     /// # Returns 
     /// particle id
-    pub fn get_grid_index(&self, pos: &Vec3A) -> usize {
+    pub fn get_grid_index(&self, pos: &Vector) -> usize {
         let grid_index = self.pos_to_index(*pos);
         let index = self.flatten_grid_index(grid_index);
 
@@ -148,6 +252,83 @@ impl ParticleSystem {
         self.flatten_grid_index(self.pos_to_index(*pos))
     }
 
+    /// Check whether position lies inside the grid, particles outside the grid can't be sorted
+    ///
+    /// # Arguments
+    /// * `pos` - worldspace position
+    pub fn is_inside_grid(&self, pos: Vector) -> bool {
+        pos.is_finite() && self.is_index_valid(self.pos_to_index(pos))
+    }
+
+    /// Collect cells neighboring the center cell along axis, periodic axes are wrapped around
+    ///
+    /// # Arguments
+    /// * `center` - center cell coordinate along axis
+    /// * `axis` - axis of the coordinate
+    ///
+    /// # Returns
+    /// unique cell coordinates and their count
+    fn neighbor_cells(&self, center: i32, axis: usize) -> ([i32; 3], usize) {
+        let mut cells = [0; 3];
+        let mut len = 0;
+
+        for offset in -1..=1 {
+            let mut cell = center + offset;
+            if self.periodic[axis] {
+                cell = cell.rem_euclid(self.grid_dims[axis]);
+            } else if cell < 0 || cell >= self.grid_dims[axis] {
+                continue;
+            }
+
+            if !cells[..len].contains(&cell) {
+                cells[len] = cell;
+                len += 1;
+            }
+        }
+
+        (cells, len)
+    }
+
+    /// Apply minimum image convention to difference vector, so periodic axes use the shortest distance
+    ///
+    /// # Arguments
+    /// * `r` - difference vector between two positions
+    pub fn minimum_image(&self, mut r: Vector) -> Vector {
+        for axis in 0..3 {
+            if self.periodic[axis] {
+                let size = self.domain_size[axis];
+                r[axis] -= size * (r[axis] / size).round();
+            }
+        }
+
+        r
+    }
+
+    /// Get difference vector between particles i and j (x_i - x_j) with respect to periodic boundaries
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    pub fn relative_position(&self, p_i: usize, p_j: usize) -> Vector {
+        self.minimum_image(self.x[p_i] - self.x[p_j])
+    }
+
+    /// Move particles, which crossed a periodic face, to the opposite face
+    pub fn wrap_periodic_positions(&mut self) {
+        if !self.periodic.contains(&true) {
+            return;
+        }
+
+        for x_i in self.x.iter_mut() {
+            for axis in 0..3 {
+                if self.periodic[axis] {
+                    let size = self.domain_size[axis];
+                    x_i[axis] = self.domain_start[axis] + (x_i[axis] - self.domain_start[axis]).rem_euclid(size);
+                }
+            }
+        }
+    }
+
     /// Check if index is not out of bounds 
     ///
     /// # Arguments 
@@ -193,29 +374,151 @@ impl ParticleSystem {
 
         for (particle_id, &new_particle_id) in new_ids.iter().enumerate() {
             new_grid_ids[new_particle_id] = self.grid_ids[particle_id];
-            self.ids_buffer[new_particle_id] = self.ids[particle_id];
-            self.x_buffer[new_particle_id] = self.x[particle_id]; 
-            self.x_0_buffer[new_particle_id] = self.x_0[particle_id]; 
-            self.v_buffer[new_particle_id] = self.v[particle_id]; 
-            self.acceleration_buffer[new_particle_id] = self.acceleration[particle_id]; 
-            self.m_v_buffer[new_particle_id] = self.m_v[particle_id]; 
-            self.m_buffer[new_particle_id] = self.m[particle_id]; 
-            self.density_buffer[new_particle_id] = self.density[particle_id]; 
-            self.pressure_buffer[new_particle_id] = self.pressure[particle_id]; 
-            self.color_buffer[new_particle_id] = self.color[particle_id]; 
         }
+
+        reorder(&mut self.ids, &mut self.ids_buffer, &new_ids);
+        reorder(&mut self.x, &mut self.x_buffer, &new_ids);
+        reorder(&mut self.x_0, &mut self.x_0_buffer, &new_ids);
+        reorder(&mut self.v, &mut self.v_buffer, &new_ids);
+        reorder(&mut self.acceleration, &mut self.acceleration_buffer, &new_ids);
+        reorder(&mut self.m_v, &mut self.m_v_buffer, &new_ids);
+        reorder(&mut self.m, &mut self.m_buffer, &new_ids);
+        reorder(&mut self.density, &mut self.density_buffer, &new_ids);
+        reorder(&mut self.pressure, &mut self.pressure_buffer, &new_ids);
+        reorder(&mut self.color, &mut self.color_buffer, &new_ids);
+        reorder(&mut self.phase, &mut self.phase_buffer, &new_ids);
+        reorder(&mut self.omega, &mut self.omega_buffer, &new_ids);
+        reorder(&mut self.temperature, &mut self.temperature_buffer, &new_ids);
+        reorder(&mut self.stress, &mut self.stress_buffer, &new_ids);
+    }
+
+    /// Check whether face of the domain is open
+    ///
+    /// # Arguments
+    /// * `axis` - axis of the face
+    /// * `positive` - whether the face is on the maximal side of the domain
+    pub fn is_face_open(&self, axis: usize, positive: bool) -> bool {
+        self.outflows.iter().any(|outflow| outflow.axis == axis && outflow.positive == positive)
+    }
+
+    /// Check whether particle lies in buffer layer of any outflow
+    ///
+    /// # Arguments
+    /// * `p_i` - particle id
+    pub fn in_outflow_buffer(&self, p_i: usize) -> bool {
+        let offset = self.domain_offset();
+        self.outflows
+            .iter()
+            .any(|outflow| outflow.in_buffer(self.x[p_i], self.domain_start + offset, self.domain_end + offset))
+    }
+
+    /// Delete particles, which entered a sink or left the domain through an open face
+    pub fn remove_drained_particles(&mut self) {
+        let offset = self.domain_offset();
+        let (domain_start, domain_end) = (self.domain_start + offset, self.domain_end + offset);
+        let keep = self.x
+            .iter()
+            .map(|x_i| {
+                !self.sinks.iter().any(|sink| sink.contains(*x_i)) 
+                    && !self.outflows.iter().any(|outflow| outflow.is_outside(*x_i, domain_start, domain_end))
+            })
+            .collect::<Vec<bool>>();
+
+        if keep.iter().all(|kept| *kept) {
+            return;
+        }
+
+        for (p_i, kept) in keep.iter().enumerate() {
+            if !kept {
+                self.removed_ids.push(self.ids[p_i]);
+            }
+        }
+
+        retain_masked(&mut self.ids, &keep);
+        retain_masked(&mut self.x, &keep);
+        retain_masked(&mut self.x_0, &keep);
+        retain_masked(&mut self.v, &keep);
+        retain_masked(&mut self.acceleration, &keep);
+        retain_masked(&mut self.m_v, &keep);
+        retain_masked(&mut self.m, &keep);
+        retain_masked(&mut self.density, &keep);
+        retain_masked(&mut self.pressure, &keep);
+        retain_masked(&mut self.color, &keep);
+        retain_masked(&mut self.phase, &keep);
+        retain_masked(&mut self.omega, &keep);
+        retain_masked(&mut self.temperature, &keep);
+        retain_masked(&mut self.stress, &keep);
         
-        for i in 0..self.particle_num {
-            self.ids[i] = self.ids_buffer[i];
-            self.x[i] = self.x_buffer[i]; 
-            self.x_0[i] = self.x_0_buffer[i]; 
-            self.v[i] = self.v_buffer[i]; 
-            self.acceleration[i] = self.acceleration_buffer[i]; 
-            self.m_v[i] = self.m_v_buffer[i]; 
-            self.m[i] = self.m_buffer[i]; 
-            self.density[i] = self.density_buffer[i]; 
-            self.pressure[i] = self.pressure_buffer[i]; 
-            self.color[i] = self.color_buffer[i]; 
+        self.particle_num = self.x.len();
+        self.grid_ids.truncate(self.particle_num);
+        self.ids_buffer.truncate(self.particle_num);
+        self.x_buffer.truncate(self.particle_num);
+        self.x_0_buffer.truncate(self.particle_num);
+        self.v_buffer.truncate(self.particle_num);
+        self.acceleration_buffer.truncate(self.particle_num);
+        self.m_v_buffer.truncate(self.particle_num);
+        self.m_buffer.truncate(self.particle_num);
+        self.density_buffer.truncate(self.particle_num);
+        self.pressure_buffer.truncate(self.particle_num);
+        self.color_buffer.truncate(self.particle_num);
+        self.phase_buffer.truncate(self.particle_num);
+        self.omega_buffer.truncate(self.particle_num);
+        self.temperature_buffer.truncate(self.particle_num);
+        self.stress_buffer.truncate(self.particle_num);
+
+        // offsets of grid cells refer to removed particles
+        self.initialize_particle_system();
+    }
+
+    /// Get current translation of the domain
+    pub fn domain_offset(&self) -> Vector {
+        self.domain_motion.as_ref().map_or(Vector::ZERO, |motion| motion.offset(self.time))
+    }
+
+    /// Get current velocity of the domain walls
+    pub fn domain_velocity(&self) -> Vector {
+        self.domain_motion.as_ref().map_or(Vector::ZERO, |motion| motion.velocity(self.time))
+    }
+
+    /// Advance simulation time, integrate dynamic rigid bodies, keep them inside the domain
+    /// and move kinematic bodies to their prescribed transforms
+    ///
+    /// # Arguments
+    /// * `delta_time` - length of time step
+    /// * `gravity` - gravitational acceleration
+    pub fn advance_boundaries(&mut self, delta_time: Real, gravity: Vector) {
+        self.time += delta_time;
+
+        let offset = self.domain_offset();
+        let min = self.domain_start + offset + Vector::splat(self.particle_radius);
+        let max = self.domain_end + offset - Vector::splat(self.particle_radius);
+
+        for body in self.rigid_bodies.iter_mut() {
+            if body.is_kinematic() {
+                body.follow_motion(self.time);
+            } else {
+                body.integrate(delta_time, gravity);
+                body.enforce_boundary(min, max);
+            }
+        }
+    }
+
+    /// Push particles out of kinematic bodies, velocity relative to the body surface is reflected
+    pub fn collide_with_kinematic_bodies(&mut self) {
+        let c_f = 0.2;
+
+        for body in self.rigid_bodies.iter().filter(|body| body.is_kinematic()) {
+            for p_i in 0..self.particle_num {
+                if let Some((point, normal)) = body.push_out(self.x[p_i], self.particle_radius) {
+                    let v_rel = self.v[p_i] - body.velocity_at(point);
+                    let v_n = v_rel.dot(normal);
+
+                    self.x[p_i] = point;
+                    if v_n < 0.0 {
+                        self.v[p_i] -= (1.0 + c_f) * v_n * normal;
+                    }
+                }
+            }
         }
     }
 
@@ -236,24 +539,19 @@ impl ParticleSystem {
         F: Fn(usize, usize, &mut T)
     {
         let center_cell = self.pos_to_index(self.x[p_i]);
+        let (x_cells, x_len) = self.neighbor_cells(center_cell.x, 0);
+        let (y_cells, y_len) = self.neighbor_cells(center_cell.y, 1);
+        let (z_cells, z_len) = self.neighbor_cells(center_cell.z, 2);
 
-        for z in -1..=1 {
-            for y in -1..=1 {
-                for x in -1..=1 {
-                    let offset = ivec3(x, y, z);
-                    let final_index = center_cell + offset;
-                    
-                    // dbg!(p_i, center_cell, offset);
-                    if !self.is_index_valid(final_index) {
-                        continue;
-                    }
-
-                    let grid_index = self.flatten_grid_index(final_index);
+        for &z in z_cells[..z_len].iter() {
+            for &y in y_cells[..y_len].iter() {
+                for &x in x_cells[..x_len].iter() {
+                    let grid_index = self.flatten_grid_index(ivec3(x, y, z));
 
                     let base_offset = self.grid_offsets[grid_index];
                     for p_j in 0..self.grid_particles_num[grid_index] {
                         let p_j = base_offset + p_j;
-                        if p_i != p_j && (self.x[p_i] - self.x[p_j]).length() < self.support_radius {
+                        if p_i != p_j && self.relative_position(p_i, p_j).length() < self.support_radius {
                             task(p_i, p_j, ret);
                         }
                     }
@@ -262,5 +560,162 @@ impl ParticleSystem {
         }
 
     }
+
+    /// Execute passed task for each particle closer than support radius to the position
+    ///
+    /// # Arguments
+    /// * `pos` - worldspace position
+    /// * `task` - task that will be executed with particle id and difference vector (x_i - pos)
+    pub fn for_all_particles_near<F>(&self, pos: Vector, mut task: F)
+    where
+        F: FnMut(usize, Vector)
+    {
+        let center_cell = ((pos - self.grid_start) / self.grid_size).floor().as_ivec3();
+        let (x_cells, x_len) = self.neighbor_cells(center_cell.x, 0);
+        let (y_cells, y_len) = self.neighbor_cells(center_cell.y, 1);
+        let (z_cells, z_len) = self.neighbor_cells(center_cell.z, 2);
+
+        for &z in z_cells[..z_len].iter() {
+            for &y in y_cells[..y_len].iter() {
+                for &x in x_cells[..x_len].iter() {
+                    let grid_index = self.flatten_grid_index(ivec3(x, y, z));
+
+                    let base_offset = self.grid_offsets[grid_index];
+                    for p_i in base_offset..base_offset + self.grid_particles_num[grid_index] {
+                        let r = self.minimum_image(self.x[p_i] - pos);
+                        if r.length() < self.support_radius {
+                            task(p_i, r);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sort ids of particles into grid cells by their current positions, particles themselves
+    /// keep their order, so the cells can be queried between sorts without invalidating per
+    /// particle arrays of solvers. Particles outside the grid are left out
+    pub fn grid_cells(&self) -> GridCells {
+        let cells = self.x[..self.particle_num]
+            .iter()
+            .map(|x| self.is_inside_grid(*x).then(|| self.get_grid_index(x)))
+            .collect::<Vec<Option<usize>>>();
+
+        let mut offsets = vec![0; self.grid_len + 1];
+        for cell in cells.iter().flatten() {
+            offsets[cell + 1] += 1;
+        }
+        for cell in 0..self.grid_len {
+            offsets[cell + 1] += offsets[cell];
+        }
+
+        let mut next = offsets.clone();
+        let mut particles = vec![0; offsets[self.grid_len]];
+        for (p_i, cell) in cells.iter().enumerate() {
+            if let Some(cell) = cell {
+                particles[next[*cell]] = p_i;
+                next[*cell] += 1;
+            }
+        }
+
+        GridCells { particles, offsets }
+    }
+
+    /// Execute passed task for each particle closer than support radius to the position, particles
+    /// are looked up in cells sorted at their current positions
+    ///
+    /// # Arguments
+    /// * `cells` - cells of particles created by `grid_cells`
+    /// * `pos` - worldspace position
+    /// * `task` - task that will be executed with particle id and difference vector (x_i - pos)
+    pub fn for_all_particles_near_in<F>(&self, cells: &GridCells, pos: Vector, mut task: F)
+    where
+        F: FnMut(usize, Vector)
+    {
+        let center_cell = ((pos - self.grid_start) / self.grid_size).floor().as_ivec3();
+        let (x_cells, x_len) = self.neighbor_cells(center_cell.x, 0);
+        let (y_cells, y_len) = self.neighbor_cells(center_cell.y, 1);
+        let (z_cells, z_len) = self.neighbor_cells(center_cell.z, 2);
+
+        for &z in z_cells[..z_len].iter() {
+            for &y in y_cells[..y_len].iter() {
+                for &x in x_cells[..x_len].iter() {
+                    let grid_index = self.flatten_grid_index(ivec3(x, y, z));
+
+                    for &p_i in cells.particles[cells.offsets[grid_index]..cells.offsets[grid_index + 1]].iter() {
+                        let r = self.minimum_image(self.x[p_i] - pos);
+                        if r.length() < self.support_radius {
+                            task(p_i, r);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Set position and color of each instance to according particle, instances of removed
+    /// particles are hidden
+    ///
+    /// # Arguments
+    /// * `instances` - instances to advect
+    pub fn advect_instances(&self, instances: &mut [Instance]) {
+        for (particle_id, instance_id) in self.ids.iter().enumerate() {
+            instances[*instance_id].position = to_single(self.x[particle_id]).into();
+            instances[*instance_id].color = self.color[particle_id].into();
+        }
+        for instance_id in self.removed_ids.iter() {
+            instances[*instance_id].position = Simulation::REMOVED.into();
+        }
+    }
+
+    /// Execute passed task for each pair of rigid body boundary particle and its neighboring particle
+    ///
+    /// # Arguments
+    /// * `task` - task that will be executed with body id, boundary particle id, particle id and
+    /// difference vector (x_i - x_b)
+    pub fn for_all_boundary_neighbors<F>(&self, mut task: F)
+    where
+        F: FnMut(usize, usize, usize, Vector)
+    {
+        for (body_id, body) in self.rigid_bodies.iter().enumerate() {
+            for (p_b, x_b) in body.boundary_world.iter().enumerate() {
+                self.for_all_particles_near(*x_b, |p_i, r| task(body_id, p_b, p_i, r));
+            }
+        }
+    }
+}
+
+/// Ids of particles sorted into grid cells without reordering the particles
+#[derive(Debug, Clone)]
+pub struct GridCells {
+    /// Particle ids ordered by cells
+    particles: Vec<usize>,
+    /// Index of the first particle of each cell in particles, the last item ends the last cell
+    offsets: Vec<usize>,
 }
 
+/// Keep only values, which have their flag set in mask
+///
+/// # Arguments
+/// * `values` - values to filter
+/// * `keep` - flag for each value
+fn retain_masked<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut index = 0;
+    values.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+}
+
+/// Move values to their new positions
+///
+/// # Arguments
+/// * `values` - values to reorder
+/// * `buffer` - helper storage of the same length as values
+/// * `new_ids` - new position (value) of each value (index)
+fn reorder<T: Copy>(values: &mut [T], buffer: &mut [T], new_ids: &[usize]) {
+    for (particle_id, &new_particle_id) in new_ids.iter().enumerate() {
+        buffer[new_particle_id] = values[particle_id];
+    }
+    values.copy_from_slice(buffer);
+}
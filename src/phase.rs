@@ -0,0 +1,40 @@
+use glam::Vec3A;
+
+use crate::{ElasticMaterial, Real};
+
+
+/// Fluid phase, particles of different phases can have different rest densities, elastic
+/// phases are deformable solids
+#[derive(Debug, Clone)]
+pub struct Phase {
+    /// Rest density
+    pub density_0: Real,
+    /// Viscosity coeficient
+    pub viscosity: Real,
+    /// Color of the phase particles
+    pub color: Vec3A,
+    /// Material of elastic solid, None for fluid
+    pub elastic: Option<ElasticMaterial>,
+}
+
+impl Phase {
+    /// Create new phase
+    ///
+    /// # Arguments
+    /// * `density_0` - rest density
+    /// * `viscosity` - viscosity coeficient
+    /// * `color` - color of the phase particles
+    pub fn new(density_0: Real, viscosity: Real, color: Vec3A) -> Self {
+        Phase { density_0, viscosity, color, elastic: None }
+    }
+
+    /// Create phase of elastic or plastic solid, which interacts with the fluid
+    ///
+    /// # Arguments
+    /// * `density_0` - rest density
+    /// * `color` - color of the phase particles
+    /// * `material` - elastic material
+    pub fn solid(density_0: Real, color: Vec3A, material: ElasticMaterial) -> Self {
+        Phase { density_0, viscosity: 0.0, color, elastic: Some(material) }
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use crate::Simulation;
+
+
+/// Computation of particle positions between recorded frames
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Interpolation {
+    /// Show the last recorded frame
+    None,
+    /// Move along straight line between frames
+    Linear,
+    /// Cubic Hermite curve matching recorded velocities at frames
+    Hermite,
+}
+
+
+/// Behaviour of playback at the ends of recording
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum PlaybackMode {
+    /// Stop at the end
+    Once,
+    /// Continue from the other end
+    Loop,
+    /// Reverse direction at both ends
+    PingPong,
+}
+
+
+/// Time based replay of recording, which is independent of the rate of drawing
+#[derive(Debug, Clone, Copy)]
+pub struct Playback {
+    /// Time of the shown state (s)
+    pub time: f32,
+    /// Speed relative to real time, negative speed plays backwards
+    pub speed: f32,
+    pub interpolation: Interpolation,
+    pub mode: PlaybackMode,
+    pub is_playing: bool,
+}
+
+impl Playback {
+    /// Create paused playback at the start of recording
+    ///
+    /// # Arguments
+    /// * `speed` - speed relative to real time
+    /// * `interpolation` - computation of positions between frames
+    /// * `mode` - behaviour at the ends of recording
+    pub fn new(speed: f32, interpolation: Interpolation, mode: PlaybackMode) -> Self {
+        Playback { time: 0.0, speed, interpolation, mode, is_playing: false }
+    }
+
+    /// Move time by elapsed real time multiplied by speed, at the ends of recording playback stops,
+    /// loops or reverses according to mode
+    ///
+    /// # Arguments
+    /// * `simulation` - replayed recording
+    /// * `elapsed` - real time since the last advance
+    pub fn advance(&mut self, simulation: &Simulation, elapsed: Duration) {
+        if !self.is_playing {
+            return;
+        }
+
+        let length = simulation.last_frame_time();
+        let time = self.time + self.speed * elapsed.as_secs_f32();
+        if length <= 0.0 || self.mode == PlaybackMode::Once {
+            self.seek(simulation, time);
+            if time <= 0.0 || time >= length {
+                self.is_playing = false;
+            }
+            return;
+        }
+
+        self.time = match self.mode {
+            PlaybackMode::Loop => time.rem_euclid(length),
+            _ => {
+                // time is reflected at each crossed end
+                if (time / length).floor() as i64 % 2 != 0 {
+                    self.speed = -self.speed;
+                }
+                let phase = time.rem_euclid(2.0 * length);
+                if phase > length { 2.0 * length - phase } else { phase }
+            }
+        };
+    }
+
+    /// Move to frame clamped to the recording
+    ///
+    /// # Arguments
+    /// * `simulation` - replayed recording
+    /// * `frame` - index of the frame
+    pub fn seek_frame(&mut self, simulation: &Simulation, frame: u32) {
+        self.seek(simulation, frame as f32 / simulation.fps as f32);
+    }
+
+    /// Move to time clamped to the recording
+    ///
+    /// # Arguments
+    /// * `simulation` - replayed recording
+    /// * `time` - requested time (s)
+    pub fn seek(&mut self, simulation: &Simulation, time: f32) {
+        self.time = time.clamp(0.0, simulation.last_frame_time());
+    }
+}
@@ -0,0 +1,92 @@
+use glam::Vec3A;
+
+
+/// Scalar and vector types of the simulation in single precision (default)
+#[cfg(not(feature = "f64"))]
+mod types {
+    /// Floating point type used by solvers, particle system and kernels
+    pub type Real = f32;
+    /// Vector type used by solvers, particle system and kernels
+    pub type Vector = glam::Vec3A;
+    /// Matrix type used by solvers, particle system and kernels
+    pub type Matrix = glam::Mat3A;
+    /// Rotation type of rigid bodies
+    pub type Rotation = glam::Quat;
+
+    pub use std::f32::consts;
+
+    /// Create vector from components
+    pub const fn vector(x: Real, y: Real, z: Real) -> Vector {
+        Vector::new(x, y, z)
+    }
+
+    /// Convert vector to single precision, which is used by the recorder and the renderer
+    pub fn to_single(v: Vector) -> glam::Vec3A {
+        v
+    }
+
+    /// Convert scalar to single precision
+    pub const fn real_to_single(value: Real) -> f32 {
+        value
+    }
+
+    /// Convert single precision vector (e.g. instance position) to simulation precision
+    pub fn from_single(v: glam::Vec3A) -> Vector {
+        v
+    }
+
+    /// Convert rotation to single precision
+    pub fn rotation_to_single(rotation: Rotation) -> glam::Quat {
+        rotation
+    }
+}
+
+/// Scalar and vector types of the simulation in double precision (feature `f64`)
+#[cfg(feature = "f64")]
+mod types {
+    /// Floating point type used by solvers, particle system and kernels
+    pub type Real = f64;
+    /// Vector type used by solvers, particle system and kernels
+    pub type Vector = glam::DVec3;
+    /// Matrix type used by solvers, particle system and kernels
+    pub type Matrix = glam::DMat3;
+    /// Rotation type of rigid bodies
+    pub type Rotation = glam::DQuat;
+
+    pub use std::f64::consts;
+
+    /// Create vector from components
+    pub const fn vector(x: Real, y: Real, z: Real) -> Vector {
+        Vector::new(x, y, z)
+    }
+
+    /// Convert vector to single precision, which is used by the recorder and the renderer
+    pub fn to_single(v: Vector) -> glam::Vec3A {
+        v.as_vec3().into()
+    }
+
+    /// Convert scalar to single precision
+    pub const fn real_to_single(value: Real) -> f32 {
+        value as f32
+    }
+
+    /// Convert single precision vector (e.g. instance position) to simulation precision
+    pub fn from_single(v: glam::Vec3A) -> Vector {
+        v.as_dvec3()
+    }
+
+    /// Convert rotation to single precision
+    pub fn rotation_to_single(rotation: Rotation) -> glam::Quat {
+        rotation.as_f32()
+    }
+}
+
+pub use types::*;
+
+/// Convert position of renderer instance to simulation precision
+///
+/// # Arguments
+/// * `position` - position of the instance
+pub fn from_instance(position: [f32; 3]) -> Vector {
+    from_single(Vec3A::from(position))
+}
@@ -0,0 +1,464 @@
+use crate::{Motion, Real, Vector, Matrix, Rotation, vector, consts};
+
+
+/// Shape of rigid body in its local space, centered at center of mass
+#[derive(Debug, Clone)]
+pub enum RigidShape {
+    /// Box given by half of its side lengths
+    Box { half_extents: Vector },
+    /// Sphere given by its radius
+    Sphere { radius: Real },
+    /// Closed triangle mesh with outward facing triangles
+    Mesh { vertices: Vec<Vector>, indices: Vec<[usize; 3]> },
+}
+
+
+/// Rigid body sampled by boundary particles, which couples with fluid in both ways
+/// (Akinci et al. 2012). Kinematic bodies follow prescribed motion instead and only push the fluid
+#[derive(Debug, Clone)]
+pub struct RigidBody {
+    pub shape: RigidShape,
+
+    pub mass: Real,
+    /// Inertia tensor in local space
+    pub inertia: Matrix,
+
+    /// Position of center of mass
+    pub position: Vector,
+    pub rotation: Rotation,
+    pub v: Vector,
+    /// Angular velocity in world space
+    pub omega: Vector,
+
+    /// Force accumulated during step
+    pub force: Vector,
+    /// Torque accumulated during step
+    pub torque: Vector,
+
+    /// Positions of boundary particles in local space
+    pub boundary_x: Vec<Vector>,
+    /// Positions of boundary particles in world space
+    pub boundary_world: Vec<Vector>,
+    /// Volume of each boundary particle
+    pub boundary_volume: Vec<Real>,
+
+    /// Prescribed motion of kinematic body
+    pub motion: Option<Motion>,
+    /// Position at time 0
+    pub initial_position: Vector,
+    /// Rotation at time 0
+    pub initial_rotation: Rotation,
+}
+
+impl RigidBody {
+    /// Create new rigid body, its mass and inertia are computed from the shape
+    ///
+    /// # Arguments
+    /// * `shape` - shape of the body
+    /// * `density` - density of the body
+    /// * `position` - initial position of center of mass
+    /// * `spacing` - distance between boundary particles (usually particle diameter)
+    pub fn new(shape: RigidShape, density: Real, position: Vector, spacing: Real) -> Self {
+        let shape = match shape {
+            RigidShape::Mesh { vertices, indices } => {
+                let (_, center, _) = mesh_mass_properties(&vertices, &indices);
+                let vertices = vertices.into_iter().map(|vertex| vertex - center).collect();
+                RigidShape::Mesh { vertices, indices }
+            }
+            shape => shape,
+        };
+
+        let (mass, inertia) = match &shape {
+            RigidShape::Box { half_extents } => {
+                let mass = density * 8.0 * half_extents.x * half_extents.y * half_extents.z;
+                let size2 = *half_extents * *half_extents;
+                let diagonal = mass / 3.0 * vector(size2.y + size2.z, size2.x + size2.z, size2.x + size2.y);
+                (mass, Matrix::from_diagonal(diagonal.into()))
+            }
+            RigidShape::Sphere { radius } => {
+                let mass = density * 4.0 / 3.0 * consts::PI * radius.powi(3);
+                (mass, Matrix::from_diagonal(Vector::splat(0.4 * mass * radius * radius).into()))
+            }
+            RigidShape::Mesh { vertices, indices } => {
+                let (volume, _, covariance) = mesh_mass_properties(vertices, indices);
+                let covariance = covariance * density;
+                let inertia = Matrix::from_diagonal(Vector::splat(
+                    covariance.x_axis.x + covariance.y_axis.y + covariance.z_axis.z
+                ).into()) - covariance;
+                (density * volume, inertia)
+            }
+        };
+
+        let boundary_x = sample_surface(&shape, spacing);
+
+        let mut body = RigidBody {
+            shape,
+            mass,
+            inertia,
+            position,
+            rotation: Rotation::IDENTITY,
+            v: Vector::ZERO,
+            omega: Vector::ZERO,
+            force: Vector::ZERO,
+            torque: Vector::ZERO,
+            boundary_world: vec![Vector::ZERO; boundary_x.len()],
+            boundary_volume: vec![0.0; boundary_x.len()],
+            boundary_x,
+            motion: None,
+            initial_position: position,
+            initial_rotation: Rotation::IDENTITY,
+        };
+        body.update_boundary();
+
+        body
+    }
+
+    /// Create new kinematic body (collider), which moves along prescribed motion
+    ///
+    /// # Arguments
+    /// * `shape` - shape of the body
+    /// * `position` - position at time 0
+    /// * `spacing` - distance between boundary particles (usually particle diameter)
+    /// * `motion` - prescribed motion
+    pub fn kinematic(shape: RigidShape, position: Vector, spacing: Real, motion: Motion) -> Self {
+        let mut body = Self::new(shape, 1.0, position, spacing);
+        body.motion = Some(motion);
+        body.follow_motion(0.0);
+
+        body
+    }
+
+    /// Check whether the body follows prescribed motion
+    pub fn is_kinematic(&self) -> bool {
+        self.motion.is_some()
+    }
+
+    /// Move kinematic body to its prescribed transform at time, accumulators are cleared
+    ///
+    /// # Arguments
+    /// * `time` - simulation time (s)
+    pub fn follow_motion(&mut self, time: Real) {
+        if let Some(motion) = &self.motion {
+            self.position = self.initial_position + motion.offset(time);
+            self.rotation = motion.rotation(time) * self.initial_rotation;
+            self.v = motion.velocity(time);
+            self.omega = motion.angular_velocity(time);
+        }
+
+        self.force = Vector::ZERO;
+        self.torque = Vector::ZERO;
+        self.update_boundary();
+    }
+
+    /// Find the closest point on surface of the body for position inside of it
+    ///
+    /// # Arguments
+    /// * `x` - worldspace position
+    /// * `padding` - distance kept from the surface
+    ///
+    /// # Returns
+    /// surface point and outward normal or None when the position is outside
+    pub fn push_out(&self, x: Vector, padding: Real) -> Option<(Vector, Vector)> {
+        let local = self.rotation.inverse() * (x - self.position);
+
+        let (local_point, local_normal) = match &self.shape {
+            RigidShape::Box { half_extents } => {
+                let extents = *half_extents + Vector::splat(padding);
+                let penetration = extents - local.abs();
+                if penetration.min_element() <= 0.0 {
+                    return None;
+                }
+
+                let axis = if penetration.x <= penetration.y && penetration.x <= penetration.z {
+                    0
+                } else if penetration.y <= penetration.z {
+                    1
+                } else {
+                    2
+                };
+                let mut normal = Vector::ZERO;
+                normal[axis] = local[axis].signum();
+                let mut point = local;
+                point[axis] = normal[axis] * extents[axis];
+
+                (point, normal)
+            }
+            RigidShape::Sphere { radius } => {
+                let distance = local.length();
+                if distance >= radius + padding || distance < 1e-6 {
+                    return None;
+                }
+
+                let normal = local / distance;
+                (normal * (radius + padding), normal)
+            }
+            RigidShape::Mesh { vertices, indices } => {
+                // positions farther than the furthest vertex can't touch the mesh
+                let bound = vertices.iter().fold(0.0, |bound: Real, vertex| bound.max(vertex.length()));
+                if local.length() >= bound + padding {
+                    return None;
+                }
+
+                let (closest, triangle) = indices
+                    .iter()
+                    .map(|triangle| {
+                        let (a, b, c) = (vertices[triangle[0]], vertices[triangle[1]], vertices[triangle[2]]);
+                        (closest_point_on_triangle(local, a, b, c), (b - a).cross(c - a))
+                    })
+                    .min_by(|(a, _), (b, _)| (local - *a).length_squared().total_cmp(&(local - *b).length_squared()))?;
+
+                let inside = mesh_contains(vertices, indices, local);
+                let distance = (local - closest).length();
+                if !inside && distance >= padding {
+                    return None;
+                }
+
+                let mut normal = if distance > 1e-6 { (local - closest) / distance } else { triangle.normalize_or_zero() };
+                if inside && distance > 1e-6 {
+                    normal = -normal;
+                }
+                (closest + normal * padding, normal)
+            }
+        };
+
+        Some((self.position + self.rotation * local_point, self.rotation * local_normal))
+    }
+
+    /// Recompute world space positions of boundary particles
+    pub fn update_boundary(&mut self) {
+        for (x_world, x_local) in self.boundary_world.iter_mut().zip(self.boundary_x.iter()) {
+            *x_world = self.position + self.rotation * *x_local;
+        }
+    }
+
+    /// Get velocity of the body at world space point
+    ///
+    /// # Arguments
+    /// * `x` - worldspace position
+    pub fn velocity_at(&self, x: Vector) -> Vector {
+        self.v + self.omega.cross(x - self.position)
+    }
+
+    /// Apply force at world space point
+    ///
+    /// # Arguments
+    /// * `force` - applied force
+    /// * `x` - worldspace point of application
+    pub fn apply_force(&mut self, force: Vector, x: Vector) {
+        self.force += force;
+        self.torque += (x - self.position).cross(force);
+    }
+
+    /// Integrate accumulated force and torque and move the body, accumulators are cleared
+    ///
+    /// # Arguments
+    /// * `delta_time` - length of time step
+    /// * `gravity` - gravitational acceleration
+    pub fn integrate(&mut self, delta_time: Real, gravity: Vector) {
+        let rotation = Matrix::from_quat(self.rotation);
+        let inertia_world = rotation * self.inertia * rotation.transpose();
+        let angular_momentum = inertia_world * self.omega;
+
+        self.v += delta_time * (self.force / self.mass + gravity);
+        self.omega += delta_time * (inertia_world.inverse() * (self.torque - self.omega.cross(angular_momentum)));
+
+        self.position += delta_time * self.v;
+        let spin = Rotation::from_xyzw(self.omega.x, self.omega.y, self.omega.z, 0.0) * self.rotation;
+        self.rotation = (self.rotation + spin * (0.5 * delta_time)).normalize();
+
+        self.force = Vector::ZERO;
+        self.torque = Vector::ZERO;
+        self.update_boundary();
+    }
+
+    /// Keep all boundary particles of the body inside box, velocity into the walls is reflected
+    ///
+    /// # Arguments
+    /// * `min` - minimal corner of the box
+    /// * `max` - maximal corner of the box
+    pub fn enforce_boundary(&mut self, min: Vector, max: Vector) {
+        let c_f = 0.2;
+        let lowest = self.boundary_world.iter().fold(Vector::splat(Real::MAX), |acc, x| acc.min(*x));
+        let highest = self.boundary_world.iter().fold(Vector::splat(Real::MIN), |acc, x| acc.max(*x));
+
+        for axis in 0..3 {
+            if lowest[axis] < min[axis] {
+                self.position[axis] += min[axis] - lowest[axis];
+                if self.v[axis] < 0.0 {
+                    self.v[axis] *= -c_f;
+                }
+            } else if highest[axis] > max[axis] {
+                self.position[axis] -= highest[axis] - max[axis];
+                if self.v[axis] > 0.0 {
+                    self.v[axis] *= -c_f;
+                }
+            }
+        }
+
+        self.update_boundary();
+    }
+}
+
+
+/// Compute volume, center of mass and covariance (relative to center of mass and for unit density)
+/// of closed triangle mesh (Blow & Binstock 2004)
+///
+/// # Arguments
+/// * `vertices` - vertices of the mesh
+/// * `indices` - triangles of the mesh
+fn mesh_mass_properties(vertices: &[Vector], indices: &[[usize; 3]]) -> (Real, Vector, Matrix) {
+    let canonical = Matrix::from_cols(
+        vector(2.0, 1.0, 1.0),
+        vector(1.0, 2.0, 1.0),
+        vector(1.0, 1.0, 2.0),
+    ) * (1.0 / 120.0);
+
+    let mut volume = 0.0;
+    let mut center = Vector::ZERO;
+    let mut covariance = Matrix::ZERO;
+
+    for triangle in indices {
+        let a = Matrix::from_cols(vertices[triangle[0]], vertices[triangle[1]], vertices[triangle[2]]);
+        let det = a.determinant();
+
+        volume += det / 6.0;
+        center += det / 6.0 * (a.x_axis + a.y_axis + a.z_axis) / 4.0;
+        covariance += a * canonical * a.transpose() * det;
+    }
+
+    center /= volume;
+    let shift = Matrix::from_cols(center * center.x, center * center.y, center * center.z);
+
+    (volume, center, covariance - shift * volume)
+}
+
+
+/// Find the closest point of triangle (Ericson 2004, Real-Time Collision Detection)
+///
+/// # Arguments
+/// * `p` - position
+/// * `a`, `b`, `c` - vertices of the triangle
+fn closest_point_on_triangle(p: Vector, a: Vector, b: Vector, c: Vector) -> Vector {
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + d1 / (d1 - d3) * ab;
+    }
+
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + d2 / (d2 - d6) * ac;
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (d4 - d3) / ((d4 - d3) + (d5 - d6)) * (c - b);
+    }
+
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * vb * denominator + ac * vc * denominator
+}
+
+
+/// Check whether closed triangle mesh contains position by parity of ray crossings
+/// (Möller & Trumbore 1997)
+///
+/// # Arguments
+/// * `vertices` - vertices of the mesh
+/// * `indices` - triangles of the mesh
+/// * `p` - position
+fn mesh_contains(vertices: &[Vector], indices: &[[usize; 3]], p: Vector) -> bool {
+    // direction is skewed, so the ray doesn't pass through edges of axis aligned meshes
+    let direction = vector(0.8, 0.4472, 0.3999).normalize();
+    let crossings = indices
+        .iter()
+        .filter(|triangle| {
+            let (a, b, c) = (vertices[triangle[0]], vertices[triangle[1]], vertices[triangle[2]]);
+            let (ab, ac) = (b - a, c - a);
+            let h = direction.cross(ac);
+            let det = ab.dot(h);
+            if det.abs() < 1e-12 {
+                return false;
+            }
+
+            let ap = p - a;
+            let u = ap.dot(h) / det;
+            let q = ap.cross(ab);
+            let v = direction.dot(q) / det;
+
+            (0.0..=1.0).contains(&u) && v >= 0.0 && u + v <= 1.0 && ac.dot(q) / det > 0.0
+        })
+        .count();
+
+    crossings % 2 == 1
+}
+
+
+/// Sample surface of the shape with points
+///
+/// # Arguments
+/// * `shape` - sampled shape
+/// * `spacing` - distance between points
+fn sample_surface(shape: &RigidShape, spacing: Real) -> Vec<Vector> {
+    let mut points = Vec::new();
+
+    match shape {
+        RigidShape::Box { half_extents } => {
+            let counts = (2.0 * *half_extents / spacing).ceil().max(Vector::ONE).as_uvec3();
+            for x in 0..=counts.x {
+                for y in 0..=counts.y {
+                    for z in 0..=counts.z {
+                        let on_surface = x == 0 || y == 0 || z == 0 || x == counts.x || y == counts.y || z == counts.z;
+                        if on_surface {
+                            let t = vector(x as Real, y as Real, z as Real) / vector(counts.x as Real, counts.y as Real, counts.z as Real);
+                            points.push((2.0 * t - Vector::ONE) * *half_extents);
+                        }
+                    }
+                }
+            }
+        }
+        RigidShape::Sphere { radius } => {
+            // fibonacci sphere
+            let count = (4.0 * consts::PI * radius * radius / (spacing * spacing)).ceil().max(1.0) as usize;
+            let golden_angle = consts::PI * (3.0 - Real::sqrt(5.0));
+            for i in 0..count {
+                let y = 1.0 - 2.0 * (i as Real + 0.5) / count as Real;
+                let ring = (1.0 - y * y).sqrt();
+                let angle = golden_angle * i as Real;
+                points.push(*radius * vector(ring * angle.cos(), y, ring * angle.sin()));
+            }
+        }
+        RigidShape::Mesh { vertices, indices } => {
+            for triangle in indices {
+                let (a, b, c) = (vertices[triangle[0]], vertices[triangle[1]], vertices[triangle[2]]);
+                let longest = (b - a).length().max((c - b).length()).max((a - c).length());
+                let n = (longest / spacing).ceil().max(1.0) as usize;
+                for i in 0..=n {
+                    for j in 0..=(n - i) {
+                        let (u, v) = (i as Real / n as Real, j as Real / n as Real);
+                        points.push(a + u * (b - a) + v * (c - a));
+                    }
+                }
+            }
+        }
+    }
+
+    points
+}
@@ -0,0 +1,259 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use fluid_renderer::{create_cube, Instance};
+use glam::vec3a;
+
+use crate::{
+    Config, Diagnostics, DiagnosticsLog, GranularSolver, Guard, GuardPolicy, GuardReport, Motion, OutflowBoundary, Phase,
+    Real, RigidBody, RigidShape, Simulation, Sink, Solver, Vector, WCSPHSolver, create_square, real_to_single, to_single,
+    vector,
+};
+
+
+/// Predefined initial setup of the simulation
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Scene {
+    /// Cube of water collapsing in the tank
+    DamBreak,
+    /// Square of water collapsing in 2D
+    #[value(name = "dam-break-2d")]
+    DamBreak2d,
+    /// Cube of dry sand collapsing into a pile
+    SandPile,
+    /// Wooden box falling into a pool, the box is coupled with water in both ways
+    FloatingBox,
+    /// Water column in 2D draining through a hole in the floor and the open right side of the domain
+    Drain,
+    /// Pool in 2D with waves made by oscillating piston paddle
+    WaveTank,
+    /// Pool in 2D inside tank, which moves from side to side
+    SloshingTank,
+    /// Block of oil in 2D falling into a pool of water, oil floats up to the surface
+    OilOnWater,
+}
+
+impl Scene {
+    /// Center of the wave tank paddle along x
+    const PADDLE_X: Real = -50.0;
+    /// Number of particle rows of the water pool under oil
+    const POOL_ROWS: u32 = 8;
+    /// Number of particles of the oil block along x and y, the block is created after the pool
+    const OIL_SIZE: (u32, u32) = (12, 8);
+
+    /// Get starting and ending point of the simulation domain
+    pub fn domain(&self) -> (Vector, Vector) {
+        (vector(-60.0, -40.0, -60.0), vector(60.0, 40.0, 60.0))
+    }
+
+    /// Get number of dimensions of the scene
+    pub fn dimensions(&self) -> u32 {
+        match self {
+            Scene::DamBreak2d | Scene::Drain | Scene::WaveTank | Scene::SloshingTank | Scene::OilOnWater => 2,
+            Scene::DamBreak | Scene::SandPile | Scene::FloatingBox => 3,
+        }
+    }
+
+    /// Check whether the scene is simulated by granular solver
+    pub fn is_granular(&self) -> bool {
+        *self == Scene::SandPile
+    }
+
+    /// Create particles of the scene
+    ///
+    /// # Arguments
+    /// * `particle_size` - radius of particle
+    /// * `particle_offset` - distance between particles relative to particle size
+    pub fn instances(&self, particle_size: Real, particle_offset: Real) -> Vec<Instance> {
+        let spacing = real_to_single(particle_size * particle_offset);
+
+        match self {
+            Scene::DamBreak2d => {
+                let (domain_start, _) = self.domain();
+                let start = to_single(domain_start + vector(2.0 * particle_size, 2.0 * particle_size, 0.0));
+                create_square((20, 20), spacing, start)
+            }
+            Scene::Drain => {
+                let (domain_start, _) = self.domain();
+                let start = to_single(domain_start + vector(2.0 * particle_size, 2.0 * particle_size, 0.0));
+                create_square((12, 16), spacing, start)
+            }
+            Scene::WaveTank => {
+                // water starts in front of the paddle
+                let (domain_start, domain_end) = self.domain();
+                let start = to_single(vector(Self::PADDLE_X + 6.0 * particle_size, domain_start.y + 2.0 * particle_size, 0.0));
+                let count = ((real_to_single(domain_end.x) - start.x) / spacing).floor() as u32;
+                create_square((count, 8), spacing, start)
+            }
+            Scene::SloshingTank => {
+                let (domain_start, domain_end) = self.domain();
+                let start = to_single(domain_start + vector(2.0 * particle_size, 2.0 * particle_size, 0.0));
+                let count = ((real_to_single(domain_end.x) - start.x) / spacing).floor() as u32;
+                create_square((count, 10), spacing, start)
+            }
+            Scene::OilOnWater => {
+                let (domain_start, domain_end) = self.domain();
+                let start = to_single(domain_start + vector(2.0 * particle_size, 2.0 * particle_size, 0.0));
+                let count = ((real_to_single(domain_end.x) - start.x) / spacing).floor() as u32;
+                let mut instances = create_square((count, Self::POOL_ROWS), spacing, start);
+
+                let oil_start = vec3a(-6.0 * spacing, start.y + (Self::POOL_ROWS + 2) as f32 * spacing, 0.0);
+                instances.extend(create_square(Self::OIL_SIZE, spacing, oil_start));
+                instances
+            }
+            Scene::DamBreak | Scene::SandPile => {
+                create_cube(0.04, (14, 14, 14), Some((spacing, spacing, spacing)), (-1.0, -1.0, -1.0))
+            }
+            Scene::FloatingBox => {
+                // pool covering the floor of the whole domain
+                let (domain_start, domain_end) = self.domain();
+                let start = to_single(domain_start + Vector::splat(particle_size));
+                let count = (to_single(domain_end - domain_start) / spacing).floor().as_uvec3() - 1;
+
+                (0..6)
+                    .flat_map(|y| (0..count.z).flat_map(move |z| (0..count.x).map(move |x| (x, y, z))))
+                    .map(|(x, y, z)| Instance {
+                        position: (start + spacing * vec3a(x as f32, y as f32, z as f32)).to_array(),
+                        color: [0.1, 0.4, 0.9],
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Create rigid bodies of the scene
+    ///
+    /// # Arguments
+    /// * `particle_size` - radius of particle
+    pub fn rigid_bodies(&self, particle_size: Real) -> Vec<RigidBody> {
+        match self {
+            Scene::FloatingBox => {
+                let shape = RigidShape::Box { half_extents: Vector::splat(8.0 * particle_size) };
+                vec![RigidBody::new(shape, 500.0, vector(0.0, 10.0, 0.0), 2.0 * particle_size)]
+            }
+            Scene::WaveTank => {
+                // piston spanning the whole depth of the domain, moving along x
+                let (domain_start, domain_end) = self.domain();
+                let half_extents = vector(2.0 * particle_size, 0.5 * (domain_end.y - domain_start.y), 0.0);
+                let motion = Motion::Oscillation { amplitude: vector(2.0 * particle_size, 0.0, 0.0), frequency: 0.3, phase: 0.0 };
+                let position = vector(Self::PADDLE_X, 0.5 * (domain_start.y + domain_end.y), 0.0);
+                vec![RigidBody::kinematic(RigidShape::Box { half_extents }, position, 2.0 * particle_size, motion)]
+            }
+            Scene::DamBreak | Scene::DamBreak2d | Scene::SandPile | Scene::Drain | Scene::SloshingTank | Scene::OilOnWater => {
+                Vec::new()
+            }
+        }
+    }
+
+    /// Create configuration of particle system for the scene
+    ///
+    /// # Arguments
+    /// * `instances` - particles of the scene
+    /// * `particle_size` - radius of particle
+    /// * `density_0` - rest density
+    pub fn config(&self, instances: &Vec<Instance>, particle_size: Real, density_0: Real) -> Config {
+        let (domain_start, domain_end) = self.domain();
+        let mut config = Config::from_instances(domain_start, domain_end, particle_size, density_0, instances);
+        config.dimensions = self.dimensions();
+        config.rigid_bodies = self.rigid_bodies(particle_size);
+
+        if *self == Scene::Drain {
+            // hole in the middle of the floor, the right side of the domain is open
+            let support_radius = 4.0 * particle_size;
+            let min = vector(support_radius, domain_start.y - support_radius, domain_start.z);
+            let max = vector(2.0 * support_radius, domain_start.y + 2.0 * particle_size, domain_end.z);
+            config.sinks = vec![Sink::Box { min, max }];
+            config.outflows = vec![OutflowBoundary::new(0, true, support_radius)];
+        }
+        if *self == Scene::OilOnWater {
+            // the last particles are the lighter and more viscous oil
+            let oil_start = instances.len() - (Self::OIL_SIZE.0 * Self::OIL_SIZE.1) as usize;
+            config.phases = vec![
+                Phase::new(density_0, 0.01, vec3a(0.1, 0.4, 0.9)),
+                Phase::new(0.8 * density_0, 0.05, vec3a(0.9, 0.7, 0.1)),
+            ];
+            config.phase = (0..instances.len()).map(|i| usize::from(i >= oil_start)).collect();
+            config.interface_tension = 1000.0;
+        }
+        if *self == Scene::SloshingTank {
+            config.domain_motion = Some(Motion::Oscillation { amplitude: vector(2.0 * particle_size, 0.0, 0.0), frequency: 0.25, phase: 0.0 });
+        }
+
+        config
+    }
+}
+
+
+/// Simulate scene without window and save the recording, diagnostics are saved next to it as CSV.
+/// Exploding steps are repeated with shorter time step
+///
+/// # Arguments
+/// * `path` - path to the recorded simulation
+/// * `fps` - frames per second of the recording
+/// * `simulation_time` - length of the simulation (s)
+/// * `fluid_step_time` - length of time step (s)
+/// * `scene` - simulated scene
+/// * `particle_size` - radius of particle
+/// * `particle_offset` - distance between particles relative to particle size
+///
+/// # Returns
+/// error of the solver or of writing the files
+pub fn simulate_headless(
+    path: String,
+    fps: u32,
+    simulation_time: f32,
+    fluid_step_time: Real,
+    scene: Scene,
+    particle_size: Real,
+    particle_offset: Real,
+) -> Result<(), Box<dyn Error>> {
+    let instances = scene.instances(particle_size, particle_offset);
+    let config = scene.config(&instances, particle_size, 1000.0);
+    let frame_stop = (simulation_time * fps as f32).round() as u32;
+    let mut simulation = Simulation::new(fps, frame_stop, instances.len() as u32);
+    let total_time = Instant::now();
+
+    let diagnostics_log = if scene.is_granular() {
+        let friction_angle: Real = 30.0;
+        let sand = GranularSolver::new(friction_angle.to_radians(), 0.0, fluid_step_time, config);
+        record(sand, &mut simulation, fluid_step_time)?
+    } else {
+        let fluid = WCSPHSolver::new(0.01, 2.0e7, 0.01, fluid_step_time, config);
+        record(fluid, &mut simulation, fluid_step_time)?
+    };
+
+    println!("Hotovo, {}s", total_time.elapsed().as_millis() as f32 / 1000.0);
+    simulation.save(path.clone())?;
+    let diagnostics_path = PathBuf::from(&path).with_extension("csv");
+    diagnostics_log.save(diagnostics_path.to_str().unwrap().to_string())?;
+
+    Ok(())
+}
+
+/// Step solver and record all frames of the simulation
+///
+/// # Arguments
+/// * `solver` - simulated solver
+/// * `simulation` - recording with set length and number of particles
+/// * `fluid_step_time` - length of time step (s)
+///
+/// # Returns
+/// diagnostics of each step or report of the explosion
+fn record<S: Solver + Clone>(mut solver: S, simulation: &mut Simulation, fluid_step_time: Real) -> Result<DiagnosticsLog, GuardReport> {
+    let steps_per_frame = (1.0 / fluid_step_time / simulation.fps as Real).ceil() as u32;
+    let guard = Guard::new(GuardPolicy::Rollback);
+    let mut diagnostics_log = DiagnosticsLog::new();
+
+    for frame in 0..simulation.frame_stop {
+        for _step in 0..steps_per_frame {
+            guard.step(&mut solver)?;
+            diagnostics_log.push(Diagnostics::compute(&solver, fluid_step_time, WCSPHSolver::G));
+        }
+
+        simulation.record_frame(frame as usize, solver.ps());
+        println!("progress: {}/{} {}%", frame + 1, simulation.frame_stop, (frame + 1) * 100 / simulation.frame_stop);
+    }
+
+    Ok(diagnostics_log)
+}
@@ -1,7 +1,37 @@
+use std::fmt;
 use std::fs::{write, read};
 
 use fluid_renderer::Instance;
-use glam::Vec3A;
+use glam::{Vec3A, Quat};
+
+use crate::{RigidBody, ParticleSystem, Real, Vector, Interpolation, heat_color, to_single, real_to_single, rotation_to_single};
+
+
+/// Recorded motion of a rigid body
+#[derive(Debug, Clone)]
+pub struct RigidBodyTrack {
+    /// Boundary particles in local space, these are drawn by the player
+    pub points: Vec<Vec3A>,
+    /// Position and rotation at each frame
+    pub transforms: Vec<(Vec3A, Quat)>,
+}
+
+
+/// Recorded scalar quantity of each particle, e.g. vorticity magnitude
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub name: String,
+    /// Values of all particles at each frame, stored the same way as frames
+    pub values: Vec<f32>,
+}
+
+
+/// Named frame of the recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    pub frame: u32,
+}
 
 
 /// Struct for easier storage of information about current simulation
@@ -11,10 +41,43 @@ pub struct Simulation {
     pub frame_stop: u32,
     pub particle_num: u32,
     pub frames: Vec<Vec3A>, 
+    /// Velocities stored the same way as frames, empty when not recorded
+    pub velocities: Vec<Vec3A>,
     pub frame_index: usize,
+    pub rigid_bodies: Vec<RigidBodyTrack>,
+    /// Color of each particle, empty when not recorded
+    pub colors: Vec<Vec3A>,
+    pub channels: Vec<Channel>,
+    /// Channel used to color particles instead of recorded colors
+    pub color_channel: Option<usize>,
+    /// Channel values mapped to blue and red
+    pub color_range: (f32, f32),
+    /// Number of dimensions of the recorded simulation, 2D simulations are viewed from the top
+    pub dimensions: u32,
+    /// Starting and ending point of the domain including its motion, missing in older recordings
+    pub domain: Option<(Vec3A, Vec3A)>,
+    /// Named frames sorted by frame
+    pub bookmarks: Vec<Bookmark>,
 } 
 
 impl Simulation {
+    /// Position recorded for particles, which were deleted from the simulation
+    pub const REMOVED: Vec3A = Vec3A::splat(f32::INFINITY);
+    /// Tag of file chunk with rigid body tracks
+    const RIGID_BODIES_CHUNK: [u8; 4] = *b"RGBD";
+    /// Tag of file chunk with particle colors
+    const COLORS_CHUNK: [u8; 4] = *b"COLR";
+    /// Tag of file chunk with one recorded channel
+    const CHANNEL_CHUNK: [u8; 4] = *b"CHNL";
+    /// Tag of file chunk with number of dimensions, missing for 3D simulations
+    const DIMENSIONS_CHUNK: [u8; 4] = *b"DIMS";
+    /// Tag of file chunk with starting and ending point of the domain
+    const DOMAIN_CHUNK: [u8; 4] = *b"DOMN";
+    /// Tag of file chunk with particle velocities
+    const VELOCITIES_CHUNK: [u8; 4] = *b"VELO";
+    /// Tag of file chunk with bookmarks
+    const BOOKMARKS_CHUNK: [u8; 4] = *b"BKMK";
+
     /// create new Simulation struct 
     ///
     /// # Arguments 
@@ -29,7 +92,16 @@ impl Simulation {
             frame_stop, 
             particle_num,
             frames,
-            frame_index: 0
+            velocities: Vec::new(),
+            frame_index: 0,
+            rigid_bodies: Vec::new(),
+            colors: Vec::new(),
+            channels: Vec::new(),
+            color_channel: None,
+            color_range: (0.0, 1.0),
+            dimensions: 3,
+            domain: None,
+            bookmarks: Vec::new(),
         } 
     }
 }
@@ -77,6 +149,34 @@ impl Simulation {
         bytes.append(&mut self.frame_stop.to_ne_bytes().to_vec());
         bytes.append(&mut self.particle_num.to_ne_bytes().to_vec());
         bytes.append(&mut bytemuck::cast_slice(self.raw_frames().as_slice()).to_vec());
+
+        if self.dimensions != 3 {
+            write_chunk(&mut bytes, Self::DIMENSIONS_CHUNK, self.dimensions.to_ne_bytes().to_vec());
+        }
+        if let Some((domain_start, domain_end)) = self.domain {
+            let corners = [domain_start.to_array(), domain_end.to_array()].concat();
+            write_chunk(&mut bytes, Self::DOMAIN_CHUNK, bytemuck::cast_slice(corners.as_slice()).to_vec());
+        }
+        if !self.rigid_bodies.is_empty() {
+            write_chunk(&mut bytes, Self::RIGID_BODIES_CHUNK, self.rigid_bodies_to_bytes());
+        }
+        if !self.velocities.is_empty() {
+            let velocities = self.velocities.iter().flat_map(|velocity| velocity.to_array()).collect::<Vec<f32>>();
+            write_chunk(&mut bytes, Self::VELOCITIES_CHUNK, bytemuck::cast_slice(velocities.as_slice()).to_vec());
+        }
+        if !self.colors.is_empty() {
+            let colors = self.colors.iter().flat_map(|color| color.to_array()).collect::<Vec<f32>>();
+            write_chunk(&mut bytes, Self::COLORS_CHUNK, bytemuck::cast_slice(colors.as_slice()).to_vec());
+        }
+        for channel in self.channels.iter() {
+            let mut payload = (channel.name.len() as u32).to_ne_bytes().to_vec();
+            payload.extend_from_slice(channel.name.as_bytes());
+            payload.extend_from_slice(bytemuck::cast_slice(channel.values.as_slice()));
+            write_chunk(&mut bytes, Self::CHANNEL_CHUNK, payload);
+        }
+        if !self.bookmarks.is_empty() {
+            write_chunk(&mut bytes, Self::BOOKMARKS_CHUNK, self.bookmarks_to_bytes());
+        }
         
         write(path, bytes)
     }
@@ -90,39 +190,440 @@ impl Simulation {
     /// Simulation or error
     pub fn from_file(path: String) -> Result<Self, std::io::Error>{
         let bytes = read(path)?;
+        if bytes.len() < 12 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Missing simulation header"));
+        }
+
         let frames_per_second = u32::from_ne_bytes(bytes[0..4].try_into().unwrap());
         let frame_stop = u32::from_ne_bytes(bytes[4..8].try_into().unwrap());
         let particle_num = u32::from_ne_bytes(bytes[8..12].try_into().unwrap());
-        let frames = Self::frames_from_bytes(bytes[12..].try_into().unwrap());
 
-        Ok(Simulation { 
+        // truncated files keep only complete positions, validate reports the missing ones
+        let frames_len = (particle_num as usize * frame_stop as usize * 12).min((bytes.len() - 12) / 12 * 12);
+        let frames_end = 12 + frames_len;
+        let frames = Self::frames_from_bytes(bytes[12..frames_end].to_vec());
+
+        let mut simulation = Simulation { 
             fps: frames_per_second, 
             frame_stop, 
             particle_num,
             frames,
+            velocities: Vec::new(),
             frame_index: 0,
-        })
+            rigid_bodies: Vec::new(),
+            colors: Vec::new(),
+            channels: Vec::new(),
+            color_channel: None,
+            color_range: (0.0, 1.0),
+            dimensions: 3,
+            domain: None,
+            bookmarks: Vec::new(),
+        };
+
+        for (tag, payload) in read_chunks(&bytes[frames_end..]) {
+            if tag == Self::RIGID_BODIES_CHUNK {
+                simulation.rigid_bodies = Self::rigid_bodies_from_bytes(payload, frame_stop as usize);
+            } else if tag == Self::VELOCITIES_CHUNK {
+                simulation.velocities = Self::frames_from_bytes(payload.to_vec());
+            } else if tag == Self::COLORS_CHUNK {
+                simulation.colors = Self::frames_from_bytes(payload.to_vec());
+            } else if tag == Self::CHANNEL_CHUNK {
+                simulation.channels.push(Self::channel_from_bytes(payload));
+            } else if tag == Self::BOOKMARKS_CHUNK {
+                simulation.bookmarks = Self::bookmarks_from_bytes(payload);
+            } else if tag == Self::DIMENSIONS_CHUNK && payload.len() == 4 {
+                simulation.dimensions = u32::from_ne_bytes(payload.try_into().unwrap());
+            } else if tag == Self::DOMAIN_CHUNK && payload.len() == 24 {
+                let corners = Self::frames_from_bytes(payload.to_vec());
+                simulation.domain = Some((corners[0], corners[1]));
+            }
+        }
+
+        Ok(simulation)
+    }
+
+    /// Store positions and velocities of particles and rigid bodies at frame, colors are stored with
+    /// the first frame. Positions are converted to single precision
+    ///
+    /// # Arguments
+    /// * `frame` - index of the frame
+    /// * `ps` - recorded particle system
+    pub fn record_frame(&mut self, frame: usize, ps: &ParticleSystem) {
+        let start_index = frame * self.particle_num as usize;
+        self.velocities.resize(self.frames.len(), Vec3A::ZERO);
+        for (particle_id, instance_id) in ps.ids.iter().enumerate() {
+            self.frames[start_index + *instance_id] = to_single(ps.x[particle_id]);
+            self.velocities[start_index + *instance_id] = to_single(ps.v[particle_id]);
+        }
+        for instance_id in ps.removed_ids.iter() {
+            self.frames[start_index + *instance_id] = Self::REMOVED;
+            self.velocities[start_index + *instance_id] = Vec3A::ZERO;
+        }
+
+        self.record_rigid_bodies(frame, &ps.rigid_bodies);
+        if frame == 0 {
+            self.record_colors(&ps.ids, &ps.color);
+            self.dimensions = ps.dimensions;
+            let extent = ps.domain_motion.as_ref().map_or(Vector::ZERO, |motion| motion.max_offset());
+            self.domain = Some((to_single(ps.domain_start - extent), to_single(ps.domain_end + extent)));
+        }
+    }
+
+    /// Store position and rotation of each rigid body at frame
+    ///
+    /// # Arguments
+    /// * `frame` - index of the frame
+    /// * `bodies` - recorded bodies
+    pub fn record_rigid_bodies(&mut self, frame: usize, bodies: &[RigidBody]) {
+        if self.rigid_bodies.len() != bodies.len() {
+            self.rigid_bodies = bodies
+                .iter()
+                .map(|body| RigidBodyTrack { 
+                    points: body.boundary_x.iter().map(|x| to_single(*x)).collect(), 
+                    transforms: vec![(to_single(body.position), rotation_to_single(body.rotation)); self.frame_stop as usize],
+                })
+                .collect();
+        }
+
+        for (track, body) in self.rigid_bodies.iter_mut().zip(bodies.iter()) {
+            if let Some(transform) = track.transforms.get_mut(frame) {
+                *transform = (to_single(body.position), rotation_to_single(body.rotation));
+            }
+        }
+    }
+
+    /// Store color of each particle
+    ///
+    /// # Arguments
+    /// * `ids` - particle id of each color
+    /// * `colors` - colors of particles
+    pub fn record_colors(&mut self, ids: &[usize], colors: &[Vec3A]) {
+        self.colors = vec![Vec3A::ONE; self.particle_num as usize];
+        for (id, color) in ids.iter().zip(colors.iter()) {
+            self.colors[*id] = *color;
+        }
+    }
+
+    /// Store value of each particle at frame into channel with name, the channel is created
+    /// when it doesn't exist yet
+    ///
+    /// # Arguments
+    /// * `name` - name of the channel
+    /// * `frame` - index of the frame
+    /// * `ids` - particle id of each value
+    /// * `values` - values of particles, these are stored in single precision
+    pub fn record_channel(&mut self, name: &str, frame: usize, ids: &[usize], values: &[Real]) {
+        let size = self.particle_num as usize * self.frame_stop as usize;
+        let channel_id = match self.channels.iter().position(|channel| channel.name == name) {
+            Some(channel_id) => channel_id,
+            None => {
+                self.channels.push(Channel { name: name.to_string(), values: vec![0.0; size] });
+                self.channels.len() - 1
+            }
+        };
+
+        let channel = &mut self.channels[channel_id];
+        channel.values.resize(size, 0.0);
+        let start_index = self.particle_num as usize * frame;
+        for (id, value) in ids.iter().zip(values.iter()) {
+            if let Some(recorded) = channel.values.get_mut(start_index + id) {
+                *recorded = real_to_single(*value);
+            }
+        }
+    }
+
+    /// Get recorded channel by its name
+    ///
+    /// # Arguments
+    /// * `name` - name of the channel
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels.iter().find(|channel| channel.name == name)
+    }
+
+    /// Color particles by values of channel, range of the colors is set to the range of values
+    ///
+    /// # Arguments
+    /// * `channel` - index of the channel, None restores recorded colors
+    pub fn set_color_channel(&mut self, channel: Option<usize>) {
+        self.color_channel = channel.filter(|channel| *channel < self.channels.len());
+
+        if let Some(channel) = self.color_channel {
+            self.color_range = self.channels[channel].values
+                .iter()
+                .filter(|value| value.is_finite())
+                .fold((f32::MAX, f32::MIN), |(min, max), value| (min.min(*value), max.max(*value)));
+        }
+    }
+
+    /// Add bookmark at frame, bookmark with the same name is replaced
+    ///
+    /// # Arguments
+    /// * `name` - name of the bookmark
+    /// * `frame` - index of the frame
+    pub fn add_bookmark(&mut self, name: &str, frame: u32) {
+        self.bookmarks.retain(|bookmark| bookmark.name != name);
+        self.bookmarks.push(Bookmark { name: name.to_string(), frame });
+        self.bookmarks.sort_by_key(|bookmark| bookmark.frame);
+    }
+
+    /// Serialize bookmarks
+    fn bookmarks_to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.bookmarks.len() as u32).to_ne_bytes().to_vec();
+        for bookmark in self.bookmarks.iter() {
+            bytes.extend(bookmark.frame.to_ne_bytes());
+            bytes.extend((bookmark.name.len() as u32).to_ne_bytes());
+            bytes.extend_from_slice(bookmark.name.as_bytes());
+        }
+
+        bytes
+    }
+
+    /// Deserialize bookmarks, incomplete bookmarks are skipped
+    ///
+    /// # Arguments
+    /// * `bytes` - payload of bookmarks chunk
+    fn bookmarks_from_bytes(bytes: &[u8]) -> Vec<Bookmark> {
+        let read_u32 = |offset: usize| bytes.get(offset..offset + 4).map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap()));
+        let count = read_u32(0).unwrap_or(0);
+        let mut bookmarks = Vec::new();
+        let mut offset = 4;
+
+        for _ in 0..count {
+            let (frame, name_len) = match (read_u32(offset), read_u32(offset + 4)) {
+                (Some(frame), Some(name_len)) => (frame, name_len as usize),
+                _ => break,
+            };
+            match bytes.get(offset + 8..offset + 8 + name_len) {
+                Some(name) => bookmarks.push(Bookmark { name: String::from_utf8_lossy(name).into_owned(), frame }),
+                None => break,
+            }
+            offset += 8 + name_len;
+        }
+
+        bookmarks
+    }
+
+    /// Deserialize channel
+    ///
+    /// # Arguments
+    /// * `bytes` - payload of channel chunk
+    fn channel_from_bytes(bytes: &[u8]) -> Channel {
+        let name_len = bytes.get(0..4).map_or(0, |chunk| u32::from_ne_bytes(chunk.try_into().unwrap()) as usize);
+        let name = bytes.get(4..4 + name_len).map_or(String::new(), |name| String::from_utf8_lossy(name).into_owned());
+        let values = bytes.get(4 + name_len..).unwrap_or_default()
+            .chunks_exact(4)
+            .map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        Channel { name, values }
+    }
+
+    /// Get bounding box of all recorded particle positions
+    ///
+    /// # Returns
+    /// minimal and maximal corner of the box
+    pub fn bounds(&self) -> (Vec3A, Vec3A) {
+        self.frames
+            .iter()
+            .filter(|position| position.is_finite())
+            .fold((Vec3A::splat(f32::MAX), Vec3A::splat(f32::MIN)), |(min, max), position| (min.min(*position), max.max(*position)))
+    }
+
+    /// Get length of the recording (s)
+    pub fn duration(&self) -> f32 {
+        self.frame_stop as f32 / self.fps as f32
+    }
+
+    /// Check consistency of the recording, files truncated during writing load without error,
+    /// so they are detected here
+    ///
+    /// # Returns
+    /// description of each problem, empty for valid recording
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let size = self.particle_num as usize * self.frame_stop as usize;
+
+        if self.fps == 0 {
+            problems.push("fps is zero".to_string());
+        }
+        if self.frames.len() != size {
+            problems.push(format!("expected {} positions, found {}", size, self.frames.len()));
+        }
+        let invalid = self.frames.iter().filter(|position| position.is_nan()).count();
+        if invalid > 0 {
+            problems.push(format!("{} positions are NaN", invalid));
+        }
+        if !self.velocities.is_empty() && self.velocities.len() != size {
+            problems.push(format!("expected {} velocities, found {}", size, self.velocities.len()));
+        }
+        if !self.colors.is_empty() && self.colors.len() != self.particle_num as usize {
+            problems.push(format!("expected {} colors, found {}", self.particle_num, self.colors.len()));
+        }
+        for channel in self.channels.iter().filter(|channel| channel.values.len() != size) {
+            problems.push(format!("channel {} has {} values instead of {}", channel.name, channel.values.len(), size));
+        }
+        for (body, track) in self.rigid_bodies.iter().enumerate() {
+            if track.transforms.len() != self.frame_stop as usize {
+                problems.push(format!("rigid body {} has {} frames instead of {}", body, track.transforms.len(), self.frame_stop));
+            }
+        }
+        for bookmark in self.bookmarks.iter().filter(|bookmark| bookmark.frame >= self.frame_stop) {
+            problems.push(format!("bookmark {} points to frame {} after the end", bookmark.name, bookmark.frame));
+        }
+        if self.dimensions != 2 && self.dimensions != 3 {
+            problems.push(format!("unsupported number of dimensions {}", self.dimensions));
+        }
+
+        problems
+    }
+
+    /// Create recording with frames taken at different rate, each frame is the closest
+    /// earlier recorded frame
+    ///
+    /// # Arguments
+    /// * `fps` - frames per second of the new recording
+    /// * `duration` - length of the new recording (s), whole recording when None
+    pub fn resample(&self, fps: u32, duration: Option<f32>) -> Simulation {
+        let duration = duration.unwrap_or(self.duration()).min(self.duration());
+        let frame_stop = (duration * fps as f32).floor() as u32;
+        let particle_num = self.particle_num as usize;
+        let source_frames = (0..frame_stop as usize)
+            .map(|frame| (frame * self.fps as usize / fps as usize).min(self.frame_stop as usize - 1))
+            .collect::<Vec<usize>>();
+
+        let mut simulation = Simulation::new(fps, frame_stop, self.particle_num);
+        simulation.frames = source_frames
+            .iter()
+            .flat_map(|source| self.frames[source * particle_num..(source + 1) * particle_num].iter().copied())
+            .collect();
+        if !self.velocities.is_empty() {
+            simulation.velocities = source_frames
+                .iter()
+                .flat_map(|source| self.velocities[source * particle_num..(source + 1) * particle_num].iter().copied())
+                .collect();
+        }
+        simulation.rigid_bodies = self.rigid_bodies
+            .iter()
+            .map(|track| RigidBodyTrack { 
+                points: track.points.clone(), 
+                transforms: source_frames.iter().filter_map(|source| track.transforms.get(*source).copied()).collect(),
+            })
+            .collect();
+        simulation.channels = self.channels
+            .iter()
+            .map(|channel| Channel {
+                name: channel.name.clone(),
+                values: source_frames
+                    .iter()
+                    .flat_map(|source| channel.values[source * particle_num..(source + 1) * particle_num].iter().copied())
+                    .collect(),
+            })
+            .collect();
+        simulation.colors = self.colors.clone();
+        simulation.dimensions = self.dimensions;
+        simulation.domain = self.domain;
+        simulation.bookmarks = self.bookmarks
+            .iter()
+            .map(|bookmark| Bookmark { name: bookmark.name.clone(), frame: bookmark.frame * fps / self.fps })
+            .filter(|bookmark| bookmark.frame < frame_stop)
+            .collect();
+
+        simulation
+    }
+
+    /// Get number of instances needed to draw particles and rigid bodies
+    pub fn instance_num(&self) -> usize {
+        self.particle_num as usize + self.rigid_bodies.iter().map(|track| track.points.len()).sum::<usize>()
+    }
+
+    /// Serialize rigid body tracks
+    fn rigid_bodies_to_bytes(&self) -> Vec<u8> {
+        let mut values = Vec::new();
+        for track in self.rigid_bodies.iter() {
+            values.extend(track.points.iter().flat_map(|point| point.to_array()));
+        }
+        for frame in 0..self.frame_stop as usize {
+            for track in self.rigid_bodies.iter() {
+                let (position, rotation) = track.transforms[frame];
+                values.extend(position.to_array());
+                values.extend(rotation.to_array());
+            }
+        }
+
+        let mut bytes = (self.rigid_bodies.len() as u32).to_ne_bytes().to_vec();
+        for track in self.rigid_bodies.iter() {
+            bytes.extend((track.points.len() as u32).to_ne_bytes());
+        }
+        bytes.extend_from_slice(bytemuck::cast_slice(values.as_slice()));
+
+        bytes
+    }
+
+    /// Deserialize rigid body tracks
+    ///
+    /// # Arguments
+    /// * `bytes` - payload of rigid bodies chunk
+    /// * `frame_num` - number of recorded frames
+    fn rigid_bodies_from_bytes(bytes: &[u8], frame_num: usize) -> Vec<RigidBodyTrack> {
+        let read_u32 = |offset: usize| bytes.get(offset..offset + 4).map(|chunk| u32::from_ne_bytes(chunk.try_into().unwrap()));
+        let body_num = read_u32(0).unwrap_or(0) as usize;
+        let point_nums = (0..body_num)
+            .map_while(|body| read_u32(4 + 4 * body).map(|point_num| point_num as usize))
+            .collect::<Vec<usize>>();
+        if point_nums.len() != body_num {
+            return Vec::new();
+        }
+        let values = bytes[4 + 4 * body_num..]
+            .chunks_exact(4)
+            .map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect::<Vec<f32>>();
+
+        // tracks with incomplete points are dropped, transforms are read until the payload ends
+        let mut offset = 0;
+        let mut tracks = Vec::with_capacity(body_num);
+        for point_num in point_nums.iter() {
+            let points = match values.get(offset..offset + 3 * point_num) {
+                Some(points) => points.chunks(3).map(Vec3A::from_slice).collect(),
+                None => return Vec::new(),
+            };
+            offset += 3 * point_num;
+
+            tracks.push(RigidBodyTrack { points, transforms: Vec::with_capacity(frame_num) });
+        }
+
+        for _frame in 0..frame_num {
+            for track in tracks.iter_mut() {
+                match values.get(offset..offset + 7) {
+                    Some(transform) => track.transforms.push((Vec3A::from_slice(&transform[..3]), Quat::from_slice(&transform[3..]))),
+                    None => return tracks,
+                }
+                offset += 7;
+            }
+        }
+
+        tracks
     }
 
-    /// Updates frame_index forward in time and sets instances' positions to the according frame
+    /// Updates frame_index forward in time and sets instances' positions to the according frame,
+    /// stepping stops at the last recorded frame
     ///  
     /// # Arguments 
     /// * `instances` - instances, which will be updated
     /// * `step_length` - amount of frames to step
     pub fn step_forward(&mut self, instances: &mut Vec<Instance>, step_length: usize) {
+        self.frame_index = (self.frame_index + step_length).min(self.recorded_frames().saturating_sub(1));
         self.update_instances(instances);
-        self.frame_index += step_length;
     }
     
     /// Updates frame_index and sets instances' positions to the according frame
-    /// only backwards in time
+    /// only backwards in time, stepping stops at the first frame
     ///  
     /// # Arguments 
     /// * `instances` - instances, which will be updated
     /// * `step_length` - amount of frames to step
     pub fn step_back(&mut self, instances: &mut Vec<Instance>, step_length: usize) {
+        self.frame_index = self.frame_index.saturating_sub(step_length).min(self.recorded_frames().saturating_sub(1));
         self.update_instances(instances);
-        self.frame_index -= step_length;
     }
 
     /// Set position of each instance to the according particle 
@@ -134,11 +635,214 @@ impl Simulation {
             return;
         }
 
-        for particle in 0..self.particle_num as usize {
-            let start_index = self.particle_num as usize * self.frame_index;
+        self.set_instances(instances, self.frame_index as f32 / self.fps as f32, Interpolation::None);
+    }
+
+    /// Set position of each instance to the according particle at time between recorded frames,
+    /// frame_index is set to the last frame before the time
+    ///
+    /// # Arguments 
+    /// * `instances` - instances, which will be updated
+    /// * `time` - shown time (s), it is clamped to the recording
+    /// * `interpolation` - computation of positions between frames
+    pub fn update_instances_at(&mut self, instances: &mut [Instance], time: f32, interpolation: Interpolation) {
+        if self.recorded_frames() == 0 {
+            return;
+        }
+
+        self.frame_index = self.frame_at(time).0;
+        self.set_instances(instances, time, interpolation);
+    }
+
+    /// Get number of frames, which are stored completely
+    pub fn recorded_frames(&self) -> usize {
+        (self.frames.len() / (self.particle_num as usize).max(1)).min(self.frame_stop as usize)
+    }
+
+    /// Get time of the last recorded frame (s)
+    pub fn last_frame_time(&self) -> f32 {
+        self.recorded_frames().saturating_sub(1) as f32 / self.fps as f32
+    }
+
+    /// Get position of particle at time between recorded frames. Frames are stored by particle id,
+    /// so the particle is matched across frames, removed particles aren't interpolated
+    ///
+    /// # Arguments
+    /// * `id` - id of the particle
+    /// * `time` - time (s), it is clamped to the recording
+    /// * `interpolation` - computation of positions between frames
+    pub fn position_at(&self, id: usize, time: f32, interpolation: Interpolation) -> Vec3A {
+        let particle_num = self.particle_num as usize;
+        let (frame, t) = self.frame_at(time);
+        let start = self.frames[frame * particle_num + id];
+        if t == 0.0 || interpolation == Interpolation::None {
+            return start;
+        }
+
+        let end = self.frames[(frame + 1) * particle_num + id];
+        if !start.is_finite() || !end.is_finite() {
+            return start;
+        }
+
+        match interpolation {
+            Interpolation::None => start,
+            Interpolation::Linear => start.lerp(end, t),
+            Interpolation::Hermite => {
+                // tangents are velocities scaled to the time between frames
+                let frame_time = 1.0 / self.fps as f32;
+                let (t2, t3) = (t * t, t * t * t);
+                (2.0 * t3 - 3.0 * t2 + 1.0) * start
+                    + (t3 - 2.0 * t2 + t) * frame_time * self.velocity(frame, id)
+                    + (3.0 * t2 - 2.0 * t3) * end
+                    + (t3 - t2) * frame_time * self.velocity(frame + 1, id)
+            }
+        }
+    }
+
+    /// Get last frame before time and relative position between it and the next frame
+    ///
+    /// # Arguments
+    /// * `time` - time (s), it is clamped to the recording
+    fn frame_at(&self, time: f32) -> (usize, f32) {
+        let last = self.recorded_frames().saturating_sub(1);
+        let position = (time * self.fps as f32).clamp(0.0, last as f32);
+        // time computed from frame index isn't exact
+        let position = if (position - position.round()).abs() < 1e-3 { position.round() } else { position };
+        let frame = (position.floor() as usize).min(last);
+
+        (frame, position - frame as f32)
+    }
+
+    /// Get velocity of particle at frame, it is estimated by difference of neighbouring frames
+    /// when velocities weren't recorded
+    ///
+    /// # Arguments
+    /// * `frame` - index of the frame
+    /// * `id` - id of the particle
+    fn velocity(&self, frame: usize, id: usize) -> Vec3A {
+        let particle_num = self.particle_num as usize;
+        if let Some(velocity) = self.velocities.get(frame * particle_num + id) {
+            return *velocity;
+        }
+
+        let previous = frame.saturating_sub(1);
+        let next = (frame + 1).min(self.recorded_frames() - 1);
+        let difference = self.frames[next * particle_num + id] - self.frames[previous * particle_num + id];
+        let velocity = difference * self.fps as f32 / (next - previous).max(1) as f32;
+
+        if velocity.is_finite() { velocity } else { Vec3A::ZERO }
+    }
+
+    /// Get position and rotation of rigid body at time between recorded frames
+    ///
+    /// # Arguments
+    /// * `track` - recorded motion of the body
+    /// * `time` - time (s), it is clamped to the recording
+    /// * `interpolation` - computation of positions between frames, rotations are always interpolated spherically
+    fn rigid_body_transform(&self, track: &RigidBodyTrack, time: f32, interpolation: Interpolation) -> (Vec3A, Quat) {
+        let (frame, t) = self.frame_at(time);
+        // transforms of incomplete recordings end early
+        let (position, rotation) = match track.transforms.get(frame).or(track.transforms.last()) {
+            Some(transform) => *transform,
+            None => (Vec3A::ZERO, Quat::IDENTITY),
+        };
+        match track.transforms.get(frame + 1) {
+            Some((next_position, next_rotation)) if t > 0.0 && interpolation != Interpolation::None => {
+                (position.lerp(*next_position, t), rotation.slerp(*next_rotation, t))
+            }
+            _ => (position, rotation),
+        }
+    }
+
+    /// Set position and color of each instance at time
+    ///
+    /// # Arguments 
+    /// * `instances` - instances, which will be updated
+    /// * `time` - shown time (s)
+    /// * `interpolation` - computation of positions between frames
+    fn set_instances(&self, instances: &mut [Instance], time: f32, interpolation: Interpolation) {
+        let start_index = self.particle_num as usize * self.frame_at(time).0;
+        for (particle, instance) in instances.iter_mut().take(self.particle_num as usize).enumerate() {
             let index = start_index + particle;
-            instances[particle].position = self.frames[index].into();
+            instance.position = self.position_at(particle, time, interpolation).into();
+            // channels of incomplete recordings may miss values, base color is shown instead
+            let value = self.color_channel
+                .and_then(|channel| self.channels.get(channel))
+                .and_then(|channel| channel.values.get(index));
+            if let Some(value) = value {
+                let (min, max) = self.color_range;
+                instance.color = heat_color(*value, min, max).into();
+            } else if let Some(color) = self.colors.get(particle) {
+                instance.color = (*color).into();
+            }
+        }
+
+        let mut instance_id = self.particle_num as usize;
+        for track in self.rigid_bodies.iter() {
+            let (position, rotation) = self.rigid_body_transform(track, time, interpolation);
+            for point in track.points.iter() {
+                instances[instance_id].position = (position + rotation * *point).into();
+                instance_id += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "fps: {}", self.fps)?;
+        writeln!(f, "frames: {}", self.frame_stop)?;
+        writeln!(f, "duration: {:.3}s", self.duration())?;
+        writeln!(f, "particles: {}", self.particle_num)?;
+        writeln!(f, "dimensions: {}", self.dimensions)?;
+        match self.domain {
+            Some((domain_start, domain_end)) => writeln!(f, "domain: {} - {}", domain_start, domain_end)?,
+            None => writeln!(f, "domain: -")?,
         }
+        writeln!(f, "rigid bodies: {}", self.rigid_bodies.len())?;
+        writeln!(f, "velocities: {}", if self.velocities.is_empty() { "no" } else { "yes" })?;
+        writeln!(f, "colors: {}", if self.colors.is_empty() { "no" } else { "yes" })?;
+        let channels = self.channels.iter().map(|channel| channel.name.as_str()).collect::<Vec<&str>>();
+        writeln!(f, "channels: {}", if channels.is_empty() { "-".to_string() } else { channels.join(", ") })?;
+        let bookmarks = self.bookmarks.iter().map(|bookmark| format!("{} ({})", bookmark.name, bookmark.frame)).collect::<Vec<String>>();
+        write!(f, "bookmarks: {}", if bookmarks.is_empty() { "-".to_string() } else { bookmarks.join(", ") })
     }
 }
 
+
+/// Append chunk of data to the end of file bytes
+///
+/// # Arguments
+/// * `bytes` - bytes of the file
+/// * `tag` - identifier of the chunk
+/// * `payload` - data of the chunk
+fn write_chunk(bytes: &mut Vec<u8>, tag: [u8; 4], payload: Vec<u8>) {
+    bytes.extend_from_slice(&tag);
+    bytes.extend((payload.len() as u64).to_ne_bytes());
+    bytes.extend(payload);
+}
+
+/// Split bytes following the frames into tagged chunks
+///
+/// # Arguments
+/// * `bytes` - bytes after the frames
+///
+/// # Returns
+/// tag and payload of each complete chunk
+fn read_chunks(bytes: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+
+    while offset + 12 <= bytes.len() {
+        let tag: [u8; 4] = bytes[offset..offset + 4].try_into().unwrap();
+        let len = u64::from_ne_bytes(bytes[offset + 4..offset + 12].try_into().unwrap()) as usize;
+        if offset + 12 + len > bytes.len() {
+            break;
+        }
+
+        chunks.push((tag, &bytes[offset + 12..offset + 12 + len]));
+        offset += 12 + len;
+    }
+
+    chunks
+}
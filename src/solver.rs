@@ -1,17 +1,22 @@
-use glam::Vec3A;
-
-use crate::ParticleSystem;
+use crate::{ParticleSystem, Real, Vector, consts};
 
 /// Trait with helpful functions for solver implmentation 
 pub trait Solver {
     /// Get support radius
-    fn support_radius(&self) -> f32;
+    fn support_radius(&self) -> Real;
     /// Get particle radius
-    fn particle_radius(&self) -> f32;
+    fn particle_radius(&self) -> Real;
     /// Get dimensions of domain
     fn dimensions(&self) -> u32;
     /// Get viscosity parameter
-    fn viscosity(&self) -> f32;
+    fn viscosity(&self) -> Real;
+    /// Get length of time step
+    fn delta_time(&self) -> Real;
+    /// Set length of time step
+    ///
+    /// # Arguments
+    /// * `delta_time` - new length of time step
+    fn set_delta_time(&mut self, delta_time: Real);
     
     /// Access reference to the particle system
     fn ps(&self) -> &ParticleSystem;
@@ -20,39 +25,39 @@ pub trait Solver {
     /// Get particle num (nubmer of particles in simulation)
     fn particle_num(&self) -> usize;
     /// Get padding of simulation domain
-    fn padding(&self) -> Vec3A;
+    fn padding(&self) -> Vector;
     /// Get domain starting point
-    fn domain_start(&self) -> Vec3A;
+    fn domain_start(&self) -> Vector;
     /// Get domain ending point
-    fn domain_size(&self) -> Vec3A;
+    fn domain_size(&self) -> Vector;
 
     /// Get density at particle
     ///
     /// # Arguments 
     /// * `p_i` - particle id
-    fn get_density(&self, p_i: usize) -> &f32;
+    fn get_density(&self, p_i: usize) -> &Real;
     /// Get velocity at particle
     ///
     /// # Arguments 
     /// * `p_i` - particle id
-    fn get_v(&self, p_i: usize) -> Vec3A;
+    fn get_v(&self, p_i: usize) -> Vector;
     /// Get mass at particle
     ///
     /// # Arguments 
     /// * `p_i` - particle id
-    fn get_m(&self, p_i: usize) -> &f32;
+    fn get_m(&self, p_i: usize) -> &Real;
     /// Get volume bound mass at particle
     ///
     /// # Arguments 
     /// * `p_i` - particle id
-    fn get_m_v(&self, p_i: usize) -> &f32;
+    fn get_m_v(&self, p_i: usize) -> &Real;
     
     /// Set velocity of particle
     ///
     /// # Arguments 
     /// * `p_i` - particle id
     /// * `vel` - new velocity
-    fn set_v(&mut self, p_i: usize, vel: Vec3A);
+    fn set_v(&mut self, p_i: usize, vel: Vector);
 
     /// Sub step is run by particle system when stepping simulation
     fn sub_step(&mut self);
@@ -61,12 +66,12 @@ pub trait Solver {
     ///
     /// # Arguments 
     /// * `r_norm` - normalized distance between particles
-    fn cubic_kernel(&self, r_norm: f32) -> f32 {
+    fn cubic_kernel(&self, r_norm: Real) -> Real {
         let h = self.support_radius();
         let mut l = match self.dimensions() {
             1 => 4.0 / 3.0,
-            2 => 40.0 / 7.0 / std::f32::consts::PI,
-            3 => 4.0 / std::f32::consts::PI,
+            2 => 40.0 / 7.0 / consts::PI,
+            3 => 8.0 / consts::PI,
             _ => 1.0
         };
 
@@ -92,16 +97,16 @@ pub trait Solver {
     ///
     /// # Arguments 
     /// * `r` - difference vector between two particles
-    fn cubic_kernel_derivative(&self, r: Vec3A) -> Vec3A  {
+    fn cubic_kernel_derivative(&self, r: Vector) -> Vector  {
         let h = self.support_radius();
         let mut l = match self.dimensions() {
             1 => 4.0 / 3.0,
-            2 => 40.0 / 7.0 / std::f32::consts::PI,
-            3 => 8.0 / std::f32::consts::PI,
+            2 => 40.0 / 7.0 / consts::PI,
+            3 => 8.0 / consts::PI,
             _ => 1.0
         };
         
-        l = 6.0 * l / h * self.dimensions() as f32;
+        l = 6.0 * l / h.powi(self.dimensions() as i32);
         let r_norm = r.length();
         let q = r_norm / h;
 
@@ -114,7 +119,38 @@ pub trait Solver {
                 l * (-factor * factor) * grad_q
             }
         } else {
-            Vec3A::ZERO
+            Vector::ZERO
+        }
+    }
+
+    /// Compute cohesion spline kernel used by surface tension (Akinci et al. 2013)
+    ///
+    /// # Arguments 
+    /// * `r_norm` - normalized distance between particles
+    fn cohesion_kernel(&self, r_norm: Real) -> Real {
+        let h = self.support_radius();
+        let l = 32.0 / (consts::PI * h.powi(9));
+
+        if r_norm > h || r_norm <= 0.0 {
+            0.0
+        } else if 2.0 * r_norm > h {
+            l * (h - r_norm).powi(3) * r_norm.powi(3)
+        } else {
+            l * (2.0 * (h - r_norm).powi(3) * r_norm.powi(3) - h.powi(6) / 64.0)
+        }
+    }
+
+    /// Compute adhesion kernel used between particles and boundary (Akinci et al. 2013)
+    ///
+    /// # Arguments 
+    /// * `r_norm` - normalized distance between particle and boundary particle
+    fn adhesion_kernel(&self, r_norm: Real) -> Real {
+        let h = self.support_radius();
+
+        if r_norm > h || 2.0 * r_norm <= h {
+            0.0
+        } else {
+            0.007 / h.powf(3.25) * (-4.0 * r_norm * r_norm / h + 6.0 * r_norm - 2.0 * h).max(0.0).powf(0.25)
         }
     }
 
@@ -124,10 +160,10 @@ pub trait Solver {
     /// * `p_i` - id of particle i
     /// * `p_j` - id of particle j
     /// * `r` - position difference between these two particles
-    fn viscosity_force(&self, p_i: usize, p_j: usize, r: Vec3A) -> Vec3A {
+    fn viscosity_force(&self, p_i: usize, p_j: usize, r: Vector) -> Vector {
         let v_xy = (self.get_v(p_i) - self.get_v(p_j)).dot(r);
 
-        2.0 * ((self.dimensions() + 2) as f32) * self.viscosity() * (self.get_m(p_j) / self.get_density(p_j)) * v_xy / (
+        2.0 * ((self.dimensions() + 2) as Real) * self.viscosity() * (self.get_m(p_j) / self.get_density(p_j)) * v_xy / (
             r.length().powi(2) * 2.0 + self.particle_radius() * self.support_radius().powi(2)) * self.cubic_kernel_derivative(r)
     }
 
@@ -136,55 +172,99 @@ pub trait Solver {
     /// # Arguments
     /// * `p_i` - particle id
     /// * `vec` - normal vector of collision surface
-    fn simulate_collisions(&mut self, p_i: usize, vec: Vec3A) {
+    /// * `wall_v` - velocity of the collision surface
+    fn simulate_collisions(&mut self, p_i: usize, vec: Vector, wall_v: Vector) {
         let c_f = 0.2;
-        let new_v = self.get_v(p_i) - (1.0 + c_f) * self.get_v(p_i).dot(vec) * vec;
+        let new_v = self.get_v(p_i) - (1.0 + c_f) * (self.get_v(p_i) - wall_v).dot(vec) * vec;
         self.set_v(p_i, new_v);
     }
 
-    /// Keeps all particles inside given domain 
+    /// Keeps all particles inside given (possibly moving) domain, except for those leaving through
+    /// open faces. Particles crossing periodic faces are moved to the opposite side
     fn enforce_boundary_3d(&mut self) {
-       for p_i in 0..self.particle_num() {
-            let mut collision_normal = Vec3A::ZERO;
+        self.enforce_boundary_axes(3);
+    }
+
+    /// Keep particles in the plane of 2D simulation and enforce boundary in x and y axis
+    fn enforce_boundary_2d(&mut self) {
+        let plane = self.domain_start().z + 0.5 * self.domain_size().z;
+        for p_i in 0..self.particle_num() {
+            self.ps_mut().x[p_i].z = plane;
+            self.ps_mut().v[p_i].z = 0.0;
+        }
+
+        self.enforce_boundary_axes(2);
+    }
 
-            let max = (self.domain_start() + self.domain_size()) - self.padding();
-            let min = self.domain_start() + self.padding();
+    /// Push particles back into the domain along first `dimensions` axes and resolve collisions with walls
+    ///
+    /// # Arguments
+    /// * `dimensions` - number of enforced axes
+    fn enforce_boundary_axes(&mut self, dimensions: usize) {
+        self.ps_mut().wrap_periodic_positions();
+
+        let offset = self.ps().domain_offset();
+        let wall_v = self.ps().domain_velocity();
+        let max = (self.domain_start() + self.domain_size()) - self.padding() + offset;
+        let min = self.domain_start() + self.padding() + offset;
+        let periodic = self.ps().periodic;
+        let open_max = [0, 1, 2].map(|axis| periodic[axis] || self.ps().is_face_open(axis, true));
+        let open_min = [0, 1, 2].map(|axis| periodic[axis] || self.ps().is_face_open(axis, false));
+
+        for p_i in 0..self.particle_num() {
+            let mut collision_normal = Vector::ZERO;
             let x_i = &mut self.ps_mut().x[p_i];
-            
-            if x_i.x > max.x {
-                collision_normal.x += 1.0;
-                x_i.x = max.x;
-            } else if x_i.x <= min.x {
-                collision_normal.x -= 1.0;
-                x_i.x = min.x; 
-            }
-            
-            if x_i.y > max.y {
-                collision_normal.y += 1.0;
-                x_i.y = max.y;
-            } else if x_i.y <= min.y {
-                collision_normal.y -= 1.0;
-                x_i.y = min.y; 
-            }
-            
-            if x_i.z > max.z {
-                collision_normal.z += 1.0;
-                x_i.z = max.z;
-            } else if x_i.z <= min.z {
-                collision_normal.z -= 1.0;
-                x_i.z = min.z; 
+
+            for axis in 0..dimensions {
+                if x_i[axis] > max[axis] && !open_max[axis] {
+                    collision_normal[axis] += 1.0;
+                    x_i[axis] = max[axis];
+                } else if x_i[axis] <= min[axis] && !open_min[axis] {
+                    collision_normal[axis] -= 1.0;
+                    x_i[axis] = min[axis];
+                }
             }
 
             if collision_normal.length() > 1e-6 {
-                self.simulate_collisions(p_i, collision_normal.normalize());
+                self.simulate_collisions(p_i, collision_normal.normalize(), wall_v);
             }
-       }
+        }
+    }
+
+    /// Compute volumes of boundary particles of all rigid bodies from their sampling density
+    fn initialize_rigid_bodies(&mut self) {
+        let volumes = self.ps().rigid_bodies
+            .iter()
+            .map(|body| {
+                body.boundary_x
+                    .iter()
+                    .map(|x_b| {
+                        let kernel_sum = body.boundary_x
+                            .iter()
+                            .map(|x_k| self.cubic_kernel((*x_b - *x_k).length()))
+                            .sum::<Real>();
+                        1.0 / kernel_sum
+                    })
+                    .collect::<Vec<Real>>()
+            })
+            .collect::<Vec<Vec<Real>>>();
+
+        for (body, volume) in self.ps_mut().rigid_bodies.iter_mut().zip(volumes) {
+            body.boundary_volume = volume;
+        }
     }
 
     /// Step simulation
     fn step(&mut self) {
         self.ps_mut().initialize_particle_system();
         self.sub_step();
-        self.enforce_boundary_3d();
+        // colliders can push particles out of the domain, so the domain is enforced last
+        self.ps_mut().collide_with_kinematic_bodies();
+        if self.dimensions() == 2 {
+            self.enforce_boundary_2d();
+        } else {
+            self.enforce_boundary_3d();
+        }
+        self.ps_mut().remove_drained_particles();
     }
 }
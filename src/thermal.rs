@@ -0,0 +1,125 @@
+use glam::Vec3A;
+
+use crate::{Real, Vector};
+
+
+/// Axis aligned box, which heats or cools particles inside it towards its temperature
+#[derive(Debug, Clone)]
+pub struct ThermalRegion {
+    pub min: Vector,
+    pub max: Vector,
+    /// Temperature of the region (°C)
+    pub temperature: Real,
+    /// Rate of the heat transfer to particles (1/s)
+    pub transfer: Real,
+}
+
+impl ThermalRegion {
+    /// Create new heated or cooled region
+    ///
+    /// # Arguments
+    /// * `min` - minimal corner of the region
+    /// * `max` - maximal corner of the region
+    /// * `temperature` - temperature of the region
+    /// * `transfer` - rate of the heat transfer
+    pub fn new(min: Vector, max: Vector, temperature: Real, transfer: Real) -> Self {
+        ThermalRegion { min, max, temperature, transfer }
+    }
+
+    /// Check whether position lies inside the region
+    ///
+    /// # Arguments
+    /// * `x` - worldspace position
+    pub fn contains(&self, x: Vector) -> bool {
+        x.cmpge(self.min).all() && x.cmple(self.max).all()
+    }
+
+    /// Get rate of temperature change of particle inside the region
+    ///
+    /// # Arguments
+    /// * `temperature` - temperature of the particle
+    pub fn heat_rate(&self, temperature: Real) -> Real {
+        self.transfer * (self.temperature - temperature)
+    }
+}
+
+
+/// Thermal properties of the fluid
+#[derive(Debug, Clone, Copy)]
+pub struct Thermal {
+    /// Thermal diffusivity (m^2/s)
+    pub diffusivity: Real,
+    /// Volumetric thermal expansion coeficient (1/°C), drives Boussinesq buoyancy
+    pub expansion: Real,
+    /// Temperature, at which the fluid has its rest density
+    pub reference_temperature: Real,
+    /// Temperature, above which the material is liquid, None disables melting
+    pub melting_temperature: Option<Real>,
+    /// Width of the temperature interval, in which the material melts
+    pub melting_range: Real,
+    /// Viscosity of the solidified material
+    pub solid_viscosity: Real,
+}
+
+impl Thermal {
+    /// Create thermal properties without melting
+    ///
+    /// # Arguments
+    /// * `diffusivity` - thermal diffusivity
+    /// * `expansion` - thermal expansion coeficient
+    /// * `reference_temperature` - temperature of rest density
+    pub fn new(diffusivity: Real, expansion: Real, reference_temperature: Real) -> Self {
+        Thermal {
+            diffusivity,
+            expansion,
+            reference_temperature,
+            melting_temperature: None,
+            melting_range: 1.0,
+            solid_viscosity: 0.0,
+        }
+    }
+
+    /// Get Boussinesq buoyancy acceleration, warmer fluid rises against gravity
+    ///
+    /// # Arguments
+    /// * `temperature` - temperature of the particle
+    /// * `gravity` - gravitational acceleration
+    pub fn buoyancy(&self, temperature: Real, gravity: Vector) -> Vector {
+        -self.expansion * (temperature - self.reference_temperature) * gravity
+    }
+
+    /// Get viscosity at temperature, below melting the viscosity smoothly approaches solid viscosity
+    ///
+    /// # Arguments
+    /// * `viscosity` - viscosity of the liquid
+    /// * `temperature` - temperature of the particle
+    pub fn viscosity(&self, viscosity: Real, temperature: Real) -> Real {
+        let melting_temperature = match self.melting_temperature {
+            Some(melting_temperature) => melting_temperature,
+            None => return viscosity,
+        };
+
+        let range = self.melting_range.max(Real::EPSILON);
+        let t = ((temperature - melting_temperature) / range + 0.5).clamp(0.0, 1.0);
+        let liquid_fraction = t * t * (3.0 - 2.0 * t);
+
+        self.solid_viscosity + liquid_fraction * (viscosity - self.solid_viscosity)
+    }
+}
+
+
+/// Map value to color from blue (min) through green to red (max)
+///
+/// # Arguments
+/// * `value` - mapped value, e.g. temperature
+/// * `min` - value mapped to blue
+/// * `max` - value mapped to red
+pub fn heat_color(value: f32, min: f32, max: f32) -> Vec3A {
+    let t = if max > min { ((value - min) / (max - min)).clamp(0.0, 1.0) } else { 0.5 };
+
+    Vec3A::new(
+        (2.0 * t - 1.0).clamp(0.0, 1.0),
+        1.0 - (2.0 * t - 1.0).abs(),
+        (1.0 - 2.0 * t).clamp(0.0, 1.0),
+    )
+}
@@ -0,0 +1,47 @@
+use crate::Real;
+
+
+/// Dependence of viscosity on shear rate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViscosityModel {
+    /// Constant viscosity given by the solver or fluid phase
+    Newtonian,
+    /// `consistency * shear_rate^(index - 1)`, shear thinning for index < 1 (paint, ketchup)
+    PowerLaw { consistency: Real, index: Real },
+    /// `viscosity_inf + (viscosity_0 - viscosity_inf) / (1 + (time_constant * shear_rate)^index)`
+    Cross { viscosity_0: Real, viscosity_inf: Real, time_constant: Real, index: Real },
+    /// `viscosity_inf + (viscosity_0 - viscosity_inf) * (1 + (time_constant * shear_rate)^2)^((index - 1) / 2)`
+    Carreau { viscosity_0: Real, viscosity_inf: Real, time_constant: Real, index: Real },
+    /// `plastic_viscosity + yield_stress / shear_rate` limited by max_viscosity (mud, toothpaste)
+    Bingham { plastic_viscosity: Real, yield_stress: Real, max_viscosity: Real },
+}
+
+impl ViscosityModel {
+    /// Shear rate, below which is the shear rate clamped to avoid infinite viscosity
+    const MIN_SHEAR_RATE: Real = 1e-3;
+
+    /// Compute effective viscosity
+    ///
+    /// # Arguments
+    /// * `viscosity` - viscosity of Newtonian fluid
+    /// * `shear_rate` - magnitude of strain rate tensor sqrt(2 D:D)
+    pub fn effective_viscosity(&self, viscosity: Real, shear_rate: Real) -> Real {
+        let shear_rate = shear_rate.max(Self::MIN_SHEAR_RATE);
+
+        match *self {
+            ViscosityModel::Newtonian => viscosity,
+            ViscosityModel::PowerLaw { consistency, index } => {
+                consistency * shear_rate.powf(index - 1.0)
+            }
+            ViscosityModel::Cross { viscosity_0, viscosity_inf, time_constant, index } => {
+                viscosity_inf + (viscosity_0 - viscosity_inf) / (1.0 + (time_constant * shear_rate).powf(index))
+            }
+            ViscosityModel::Carreau { viscosity_0, viscosity_inf, time_constant, index } => {
+                viscosity_inf + (viscosity_0 - viscosity_inf) * (1.0 + (time_constant * shear_rate).powi(2)).powf((index - 1.0) / 2.0)
+            }
+            ViscosityModel::Bingham { plastic_viscosity, yield_stress, max_viscosity } => {
+                (plastic_viscosity + yield_stress / shear_rate).min(max_viscosity)
+            }
+        }
+    }
+}
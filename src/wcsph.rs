@@ -1,44 +1,114 @@
 use fluid_renderer::Instance;
-use glam::{vec3a, Vec3A};
+use crate::{Real, Vector, Matrix, vector, Solver, ParticleSystem, Config, ViscosityModel, Thermal, Elasticity, Integrator, IntegratorState};
 
-use crate::{Solver, ParticleSystem, Config};
+
+/// Model of surface tension used by the solver
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SurfaceTensionModel {
+    /// Pairwise attraction scaled by smoothing kernel
+    Simple,
+    /// Cohesion and curvature terms with boundary adhesion (Akinci et al. 2013)
+    Akinci,
+}
+
+
+/// Method of viscosity integration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViscosityMethod {
+    /// Viscosity force is added to the acceleration, unstable for high viscosities
+    Explicit,
+    /// Velocities are solved from implicit viscosity equation by conjugate gradients (Weiler et al. 2018)
+    Implicit,
+}
+
+
+/// Model, which restores small scale vortices dissipated by SPH
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VorticityModel {
+    None,
+    /// Vorticity confinement force pushes along gradient of vorticity magnitude
+    Confinement,
+    /// Micropolar fluid with angular velocity of particles (Bender et al. 2017)
+    Micropolar,
+}
 
 
 /// Weakly Compresible Smoothed Particle Hydrodynamics solver, stores
 /// current state of fluid and provides functions to manipulate with 
 /// fluid's particles
+#[derive(Debug, Clone)]
 pub struct WCSPHSolver {
     ps: ParticleSystem,
     
-    pub viscosity: f32,
-    pub density_0: f32,
-
-    pub stiffness: f32,
-    pub surface_tension: f32,
-    pub delta_time: f32,
+    pub viscosity: Real,
+    pub viscosity_method: ViscosityMethod,
+    pub viscosity_model: ViscosityModel,
+    /// Maximal number of conjugate gradient iterations of implicit viscosity
+    pub viscosity_iterations: usize,
+    /// Relative residual at which the implicit viscosity solve stops
+    pub viscosity_tolerance: Real,
+    pub density_0: Real,
+
+    pub stiffness: Real,
+    pub surface_tension: Real,
+    /// Simple pairwise model by default, Akinci model is enabled by the UI and scenes
+    pub surface_tension_model: SurfaceTensionModel,
+    /// Adhesion between fluid and rigid bodies, used by Akinci model
+    pub adhesion: Real,
+    pub delta_time: Real,
+    /// Time integration scheme of particle positions and velocities
+    pub integrator: Integrator,
+    pub vorticity_model: VorticityModel,
+    /// Confinement strength or micropolar transfer coeficient
+    pub vorticity_strength: Real,
+    /// Microinertia of micropolar model
+    pub micropolar_inertia: Real,
+    /// Heat conduction, buoyancy and melting, None disables temperature
+    pub thermal: Option<Thermal>,
+    /// Elastic solids given by elastic phases, None when no phase is elastic
+    pub elasticity: Option<Elasticity>,
+
+    integrator_state: IntegratorState,
+    normals: Vec<Vector>,
+    angular_acceleration: Vec<Vector>,
+    temperature_rate: Vec<Real>,
+    /// Vorticity (curl of velocity) of each particle
+    pub vorticity: Vec<Vector>,
+    /// Strain rate tensor of each particle, computed for non-Newtonian fluids
+    pub strain_rate: Vec<Matrix>,
+    /// Viscosity of each particle, computed for non-Newtonian fluids
+    pub effective_viscosity: Vec<Real>,
 }
 
 impl WCSPHSolver {
-    const G: Vec3A = vec3a(0.0, -9.81, 0.0);
+    pub const G: Vector = vector(0.0, -9.81, 0.0);
 }
 
 impl Solver for WCSPHSolver {
-    fn support_radius(&self) -> f32 {
+    fn support_radius(&self) -> Real {
         self.ps.support_radius
     }
 
-    fn particle_radius(&self) -> f32 {
+    fn particle_radius(&self) -> Real {
         self.ps.particle_radius
     }
 
     fn dimensions(&self) -> u32 {
-        3
+        self.ps.dimensions
     }
 
-    fn viscosity(&self) -> f32 {
+    fn viscosity(&self) -> Real {
         self.viscosity
     }
 
+    fn delta_time(&self) -> Real {
+        self.delta_time
+    }
+
+    fn set_delta_time(&mut self, delta_time: Real) {
+        self.delta_time = delta_time;
+    }
+
     
     fn ps(&self) -> &ParticleSystem {
         &self.ps
@@ -52,44 +122,66 @@ impl Solver for WCSPHSolver {
         self.ps.particle_num
     }
 
-    fn padding(&self) -> Vec3A {
-        Vec3A::splat(self.ps.particle_radius)
+    fn padding(&self) -> Vector {
+        Vector::splat(self.ps.particle_radius)
     }
 
-    fn domain_size(&self) -> Vec3A {
+    fn domain_size(&self) -> Vector {
         self.ps.domain_size
     }
 
 
-    fn get_density(&self, p_i: usize) -> &f32 {
+    fn get_density(&self, p_i: usize) -> &Real {
         &self.ps.density[p_i]
     }
 
-    fn get_v(&self, p_i: usize) -> Vec3A {
+    fn get_v(&self, p_i: usize) -> Vector {
         self.ps.v[p_i]
     }
 
-    fn get_m(&self, p_i: usize) -> &f32 {
+    fn get_m(&self, p_i: usize) -> &Real {
         &self.ps.m[p_i]
     }
     
-    fn get_m_v(&self, p_i: usize) -> &f32 {
+    fn get_m_v(&self, p_i: usize) -> &Real {
         &self.ps.m_v[p_i]
     }
 
-    fn set_v(&mut self, p_i: usize, vel: glam::Vec3A) {
+    fn set_v(&mut self, p_i: usize, vel: Vector) {
         self.ps.v[p_i] = vel
     }
 
-    fn domain_start(&self) -> Vec3A {
+    fn domain_start(&self) -> Vector {
         self.ps.domain_start
     }
     
     fn sub_step(&mut self) {
-        self.compute_densities();
-        self.compute_non_pressure_forces();
-        self.compute_pressure_forces();
-        self.advect();
+        // implicit viscosity is split from the other forces and solved first, at positions of the
+        // start of the step, so that all integrators see the same velocities
+        let viscous_forces = if self.viscosity_method == ViscosityMethod::Implicit {
+            self.compute_densities();
+            self.compute_strain_rates();
+            self.solve_implicit_viscosity();
+            self.ps.rigid_bodies
+                .iter_mut()
+                .map(|body| (std::mem::take(&mut body.force), std::mem::take(&mut body.torque)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let integrator = self.integrator;
+        let mut integrator_state = std::mem::take(&mut self.integrator_state);
+        integrator.step(self, &mut integrator_state, self.delta_time, Self::compute_accelerations);
+        self.integrator_state = integrator_state;
+
+        for (body, (force, torque)) in self.ps.rigid_bodies.iter_mut().zip(viscous_forces) {
+            body.force += force;
+            body.torque += torque;
+        }
+
+        self.advect_fields();
+        self.ps.advance_boundaries(self.delta_time, Self::G);
     }
 }
 
@@ -103,23 +195,90 @@ impl WCSPHSolver {
     /// * `delta_time` - length of time step (s) 
     /// * `particle_config` - configuration of particle system
     pub fn new(
-        viscosity: f32, 
-        stiffness: f32, 
-        surface_tension: f32, 
-        delta_time: f32, 
+        viscosity: Real, 
+        stiffness: Real, 
+        surface_tension: Real, 
+        delta_time: Real, 
         particle_config: Config
     ) -> Self {
         let density_0 = particle_config.density_0;
         let mut ps = ParticleSystem::new(particle_config);
         ps.initialize_particle_system();
 
-        WCSPHSolver { 
+        let mut solver = WCSPHSolver { 
             ps, 
             viscosity, 
+            viscosity_method: ViscosityMethod::Explicit,
+            viscosity_model: ViscosityModel::Newtonian,
+            viscosity_iterations: 100,
+            viscosity_tolerance: 1e-4,
             density_0, 
             stiffness, 
             surface_tension, 
-            delta_time 
+            surface_tension_model: SurfaceTensionModel::Simple,
+            adhesion: 0.0,
+            delta_time,
+            integrator: Integrator::SymplecticEuler,
+            vorticity_model: VorticityModel::None,
+            vorticity_strength: 0.0,
+            micropolar_inertia: 2.0,
+            thermal: None,
+            elasticity: None,
+            integrator_state: IntegratorState::default(),
+            normals: Vec::new(),
+            angular_acceleration: Vec::new(),
+            temperature_rate: Vec::new(),
+            vorticity: Vec::new(),
+            strain_rate: Vec::new(),
+            effective_viscosity: Vec::new(),
+        };
+        solver.initialize_rigid_bodies();
+
+        let materials = solver.ps.phases.iter().map(|phase| phase.elastic).collect::<Vec<_>>();
+        if materials.iter().any(|material| material.is_some()) {
+            solver.elasticity = Some(Elasticity::new(&solver, materials));
+        }
+
+        solver
+    }
+
+    /// Get rest density of particle's phase
+    ///
+    /// # Arguments
+    /// * `p_i` - particle id
+    pub fn rest_density(&self, p_i: usize) -> Real {
+        match self.ps.phases.get(self.ps.phase[p_i]) {
+            Some(phase) => phase.density_0,
+            None => self.density_0,
+        }
+    }
+
+    /// Get viscosity of particle, either its phase viscosity or effective viscosity of
+    /// non-Newtonian fluid, adjusted by its temperature
+    ///
+    /// # Arguments
+    /// * `p_i` - particle id
+    pub fn particle_viscosity(&self, p_i: usize) -> Real {
+        let viscosity = if self.viscosity_model != ViscosityModel::Newtonian {
+            self.effective_viscosity[p_i]
+        } else {
+            self.phase_viscosity(p_i)
+        };
+
+        match self.thermal {
+            Some(thermal) => thermal.viscosity(viscosity, self.ps.temperature[p_i]),
+            None => viscosity,
+        }
+    }
+
+    /// Get Newtonian viscosity of particle's phase
+    ///
+    /// # Arguments
+    /// * `p_i` - particle id
+    fn phase_viscosity(&self, p_i: usize) -> Real {
+        match self.ps.phases.get(self.ps.phase[p_i]) {
+            Some(phase) => phase.viscosity,
+            None => self.viscosity,
         }
     }
 
@@ -129,21 +288,32 @@ impl WCSPHSolver {
     /// * `p_i` - id of particle i
     /// * `p_j` - id of particle j
     /// * `ret` - mutable reference, where the result will be added
-    fn compute_densities_task(&self, p_i: usize, p_j: usize, ret: &mut f32) {
-        let x_i = self.ps.x[p_i];
-        let x_j = self.ps.x[p_j];
+    fn compute_densities_task(&self, p_i: usize, p_j: usize, ret: &mut Real) {
+        let r = self.ps.relative_position(p_i, p_j);
 
-        *ret += self.ps.m_v[p_j] * self.cubic_kernel((x_i - x_j).length());
+        *ret += self.ps.m_v[p_j] * self.cubic_kernel(r.length());
+    }
+
+    /// Updates density, pressure and acceleration of each particle
+    pub fn compute_accelerations(&mut self) {
+        self.compute_densities();
+        self.compute_non_pressure_forces();
+        self.compute_pressure_forces();
     }
 
     /// Updates density for each particle 
     pub fn compute_densities(&mut self) {
+        let mut boundary_density = vec![0.0; self.particle_num()];
+        self.ps.for_all_boundary_neighbors(|body_id, p_b, p_i, r| {
+            boundary_density[p_i] += self.ps.rigid_bodies[body_id].boundary_volume[p_b] * self.cubic_kernel(r.length());
+        });
+
         for p_i in 0..self.particle_num() {
             self.ps.density[p_i] = self.ps.m_v[p_i] * self.cubic_kernel(0.0);
             let mut density_i = 0.0;
             self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_densities_task(p_i, p_j, ret), &mut density_i);
-            self.ps.density[p_i] += density_i;
-            self.ps.density[p_i] *= self.density_0;
+            self.ps.density[p_i] += density_i + boundary_density[p_i];
+            self.ps.density[p_i] *= self.rest_density(p_i);
         }
     }
 
@@ -153,27 +323,136 @@ impl WCSPHSolver {
     /// * `p_i` - id of particle i
     /// * `p_j` - id of particle j
     /// * `ret` - mutable reference, where the result will be added
-    fn compute_pressure_forces_task(&self, p_i: usize, p_j: usize, ret: &mut Vec3A) {
-        let x_i = self.ps.x[p_i];
-        let dpi = self.ps.pressure[p_i] / self.ps.density[p_i].powi(2);
+    fn compute_pressure_forces_task(&self, p_i: usize, p_j: usize, ret: &mut Vector) {
+        let r = self.ps.relative_position(p_i, p_j);
+
+        if !self.ps.phases.is_empty() {
+            // Solenthaler & Pajarola 2008, pressure terms use number densities (density / mass)
+            let m_i = self.ps.m[p_i];
+            let m_j = self.ps.m[p_j];
+            let dpi = self.ps.pressure[p_i] * m_i / self.ps.density[p_i].powi(2);
+            let dpj = self.ps.pressure[p_j] * m_j * m_j / (m_i * self.ps.density[p_j].powi(2));
+
+            *ret += -(dpi + dpj) * self.cubic_kernel_derivative(r);
+            return;
+        }
 
-        let x_j = self.ps.x[p_j];
-        let dpj = self.ps.density[p_j] / self.ps.density[p_j].powi(2);
+        let dpi = self.ps.pressure[p_i] / self.ps.density[p_i].powi(2);
+        let dpj = self.ps.pressure[p_j] / self.ps.density[p_j].powi(2);
 
-        *ret += -self.density_0 * self.ps.m_v[p_j] * (dpi + dpj) * self.cubic_kernel_derivative(x_i - x_j);
+        *ret += -self.density_0 * self.ps.m_v[p_j] * (dpi + dpj) * self.cubic_kernel_derivative(r);
     }
 
     /// Updates pressure forces for each particle
     pub fn compute_pressure_forces(&mut self) {
         for p_i in 0..self.ps.x.len() {
-            self.ps.density[p_i] = self.ps.density[p_i].max(self.density_0);
-            self.ps.pressure[p_i] = self.stiffness * ((self.ps.density[p_i] / self.density_0) - 1.0);
+            let density_0 = self.rest_density(p_i);
+            self.ps.density[p_i] = self.ps.density[p_i].max(density_0);
+            self.ps.pressure[p_i] = self.stiffness * ((self.ps.density[p_i] / density_0) - 1.0);
         }
+        self.update_outflow_buffers();
+
         for p_i in 0..self.ps.x.len() {
-            let mut dv = Vec3A::ZERO;
+            if self.ps.in_outflow_buffer(p_i) {
+                self.ps.acceleration[p_i] = Vector::ZERO;
+                continue;
+            }
+
+            let mut dv = Vector::ZERO;
             self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_pressure_forces_task(p_i, p_j, ret), &mut dv);
             self.ps.acceleration[p_i] += dv;
         }
+
+        self.compute_boundary_forces();
+    }
+
+    /// Computes pressure, viscosity and adhesion forces between particles and boundary particles of
+    /// rigid bodies, particles are accelerated and the opposite forces are applied to the bodies
+    pub fn compute_boundary_forces(&mut self) {
+        if self.ps.rigid_bodies.is_empty() {
+            return;
+        }
+
+        let mut accelerations = vec![Vector::ZERO; self.particle_num()];
+        let mut body_forces = Vec::new();
+
+        self.ps.for_all_boundary_neighbors(|body_id, p_b, p_i, r| {
+            if self.ps.in_outflow_buffer(p_i) {
+                return;
+            }
+
+            let body = &self.ps.rigid_bodies[body_id];
+            let volume = body.boundary_volume[p_b];
+            let x_b = body.boundary_world[p_b];
+            let gradient = self.cubic_kernel_derivative(r);
+
+            let dpi = self.ps.pressure[p_i] / self.ps.density[p_i].powi(2);
+            let pressure_acceleration = -self.rest_density(p_i) * volume * dpi * gradient;
+
+            // implicit viscosity includes the boundary in its system
+            let viscosity_acceleration = if self.viscosity_method == ViscosityMethod::Explicit {
+                2.0 * self.particle_viscosity(p_i) * volume * (self.ps.v[p_i] - body.velocity_at(x_b)) * r.dot(gradient) / (
+                    r.length().powi(2) + 0.01 * self.ps.support_radius.powi(2))
+            } else {
+                Vector::ZERO
+            };
+
+            let adhesion_acceleration = match self.surface_tension_model {
+                SurfaceTensionModel::Akinci => {
+                    -self.adhesion * self.rest_density(p_i) * volume * self.adhesion_kernel(r.length()) * r.normalize_or_zero()
+                }
+                SurfaceTensionModel::Simple => Vector::ZERO,
+            };
+
+            let acceleration = pressure_acceleration + viscosity_acceleration + adhesion_acceleration;
+            accelerations[p_i] += acceleration;
+            body_forces.push((body_id, -self.ps.m[p_i] * acceleration, x_b));
+        });
+
+        for (p_i, acceleration) in accelerations.into_iter().enumerate() {
+            self.ps.acceleration[p_i] += acceleration;
+        }
+        for (body_id, force, x_b) in body_forces {
+            self.ps.rigid_bodies[body_id].apply_force(force, x_b);
+        }
+    }
+
+    /// Adds kernel weighted pressure and velocity of fluid particle j to the sums of particle i
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference to (weight, pressure, velocity) sums
+    fn extrapolate_outflow_task(&self, p_i: usize, p_j: usize, ret: &mut (Real, Real, Vector)) {
+        if self.ps.in_outflow_buffer(p_j) {
+            return;
+        }
+
+        let w = self.cubic_kernel(self.ps.relative_position(p_i, p_j).length());
+        ret.0 += w;
+        ret.1 += w * self.ps.pressure[p_j];
+        ret.2 += w * self.ps.v[p_j];
+    }
+
+    /// Extrapolates pressure and velocity of particles in outflow buffer layers from the fluid
+    fn update_outflow_buffers(&mut self) {
+        if self.ps.outflows.is_empty() {
+            return;
+        }
+
+        for p_i in 0..self.particle_num() {
+            if !self.ps.in_outflow_buffer(p_i) {
+                continue;
+            }
+
+            let mut sums = (0.0, 0.0, Vector::ZERO);
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.extrapolate_outflow_task(p_i, p_j, ret), &mut sums);
+
+            if sums.0 > 0.0 {
+                self.ps.pressure[p_i] = sums.1 / sums.0;
+                self.ps.v[p_i] = sums.2 / sums.0;
+            }
+        }
     }
 
     /// Computes non-pressure forces acting on particle i from particle j and adds the result to
@@ -183,45 +462,394 @@ impl WCSPHSolver {
     /// * `p_i` - id of particle i
     /// * `p_j` - id of particle j
     /// * `ret` - mutable reference, where the result will be added
-    fn compute_non_pressure_forces_task(&self, p_i: usize, p_j: usize, ret: &mut Vec3A) {
-        let x_i = self.ps.x[p_i];
-        let x_j = self.ps.x[p_j];
+    fn compute_non_pressure_forces_task(&self, p_i: usize, p_j: usize, ret: &mut Vector) {
+        let r = self.ps.relative_position(p_i, p_j);
+
+        // Compute Surface Tension, solids keep their shape by elasticity
+        let is_elastic = |p: usize| self.elasticity.as_ref().is_some_and(|elasticity| elasticity.is_elastic(&self.ps, p));
+        match self.surface_tension_model {
+            _ if is_elastic(p_i) || is_elastic(p_j) => {}
+            SurfaceTensionModel::Simple => {
+                // overlapping particles are attracted as if they were one diameter apart,
+                // the kernel peak at zero distance would pull them together even more
+                let kernel = self.cubic_kernel(r.length().max(self.ps.particle_diameter));
+                *ret -= self.surface_tension / self.ps.m[p_i] * self.ps.m[p_j] * r * kernel;
+            }
+            SurfaceTensionModel::Akinci => {
+                // symmetric correction, amplifies the forces at the free surface, where density is lower
+                let k_ij = 2.0 * self.rest_density(p_i) / (self.ps.density[p_i] + self.ps.density[p_j]);
+                let cohesion = -self.ps.m[p_j] * self.cohesion_kernel(r.length()) * r.normalize_or_zero();
+                let curvature = -(self.normals[p_i] - self.normals[p_j]);
+
+                *ret += self.surface_tension * k_ij * (cohesion + curvature);
+            }
+        }
+
+        // Interface tension, pushes apart particles of different phases
+        if self.ps.phase[p_i] != self.ps.phase[p_j] {
+            *ret += self.ps.interface_tension / self.ps.m[p_i] * self.ps.m[p_j] * r.normalize_or_zero() * self.cubic_kernel(r.length());
+        }
 
-        // Compute Surface Tension
-        let diam2 = self.ps.particle_diameter.powi(2);
+        // Viscosity Force, Laplacian of each velocity component (Morris et al. 1997). Laplacian of
+        // velocity projected on r underestimates shear by a third with support of two spacings
+        if self.viscosity_method == ViscosityMethod::Implicit {
+            return;
+        }
 
-        let r = x_i - x_j;
-        let r2 = r.dot(r);
+        let viscosity = 0.5 * (self.particle_viscosity(p_i) + self.particle_viscosity(p_j));
+        let f_v = 2.0 * viscosity * (self.ps.m[p_j] / (self.ps.density[p_j])) * (self.ps.v[p_i] - self.ps.v[p_j]) * r.dot(
+            self.cubic_kernel_derivative(r)) / (r.length().powi(2) + 0.01 * self.ps.support_radius.powi(2));
+        *ret += f_v;
+    }
 
-        if r2 > diam2 {
-            *ret -= self.surface_tension / self.ps.m[p_i] * self.ps.m[p_j] * r * self.cubic_kernel(r.length());
-        } else {
-            *ret -= self.surface_tension / self.ps.m[p_i] * self.ps.m[p_j] * r * self.cubic_kernel(self.ps.particle_diameter); // possible bug
+    /// Computes contribution of particle j to the surface normal of particle i and adds it to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_normals_task(&self, p_i: usize, p_j: usize, ret: &mut Vector) {
+        let r = self.ps.relative_position(p_i, p_j);
+        *ret += self.ps.m[p_j] / self.ps.density[p_j] * self.cubic_kernel_derivative(r);
+    }
+
+    /// Updates surface normal (not normalized, zero inside of fluid) of each particle
+    pub fn compute_normals(&mut self) {
+        self.normals.resize(self.particle_num(), Vector::ZERO);
+
+        for p_i in 0..self.particle_num() {
+            let mut normal = Vector::ZERO;
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_normals_task(p_i, p_j, ret), &mut normal);
+            self.normals[p_i] = self.ps.support_radius * normal;
+        }
+    }
+
+    /// Computes contribution of particle j to the velocity gradient of particle i and adds it to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_velocity_gradient_task(&self, p_i: usize, p_j: usize, ret: &mut Matrix) {
+        let r = self.ps.relative_position(p_i, p_j);
+        let gradient = self.cubic_kernel_derivative(r);
+        let dv = (self.ps.m[p_j] / self.ps.density[p_j]) * (self.ps.v[p_j] - self.ps.v[p_i]);
+
+        *ret += Matrix::from_cols(dv * gradient.x, dv * gradient.y, dv * gradient.z);
+    }
+
+    /// Updates strain rate tensor and effective viscosity of each particle, only used by
+    /// non-Newtonian fluids
+    pub fn compute_strain_rates(&mut self) {
+        if self.viscosity_model == ViscosityModel::Newtonian {
+            return;
         }
 
-        // Viscosity Force
-        let d = 2.0 * (self.dimensions() + 2) as f32;
-        let v_xy = (self.ps.v[p_i] - self.ps.v[p_j]).dot(r);
+        self.strain_rate.resize(self.particle_num(), Matrix::ZERO);
+        self.effective_viscosity.resize(self.particle_num(), 0.0);
 
-        let f_v = d * self.viscosity * (self.ps.m[p_j] / (self.ps.density[p_j])) * v_xy / (
-            r.length().powi(2) + 0.01 * self.ps.support_radius.powi(2)) * self.cubic_kernel_derivative(r);
-        *ret += f_v;
+        for p_i in 0..self.particle_num() {
+            let mut velocity_gradient = Matrix::ZERO;
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_velocity_gradient_task(p_i, p_j, ret), &mut velocity_gradient);
+
+            let strain_rate = (velocity_gradient + velocity_gradient.transpose()) * 0.5;
+            let shear_rate = (2.0 * (
+                strain_rate.x_axis.length_squared() + strain_rate.y_axis.length_squared() + strain_rate.z_axis.length_squared()
+            )).sqrt();
+
+            self.strain_rate[p_i] = strain_rate;
+            self.effective_viscosity[p_i] = self.viscosity_model.effective_viscosity(self.phase_viscosity(p_i), shear_rate);
+        }
+    }
+
+    /// Computes contribution of particle j to the vorticity of particle i and adds it to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_vorticity_task(&self, p_i: usize, p_j: usize, ret: &mut Vector) {
+        let r = self.ps.relative_position(p_i, p_j);
+        let volume = self.ps.m[p_j] / self.ps.density[p_j];
+
+        *ret += volume * (self.ps.v[p_i] - self.ps.v[p_j]).cross(self.cubic_kernel_derivative(r));
+    }
+
+    /// Updates vorticity of each particle
+    pub fn compute_vorticity(&mut self) {
+        self.vorticity.resize(self.particle_num(), Vector::ZERO);
+
+        for p_i in 0..self.particle_num() {
+            let mut vorticity = Vector::ZERO;
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_vorticity_task(p_i, p_j, ret), &mut vorticity);
+            self.vorticity[p_i] = vorticity;
+        }
+    }
+
+    /// Computes contribution of particle j to the vorticity magnitude gradient (confinement) or to
+    /// the curl of angular velocity (micropolar) of particle i and adds it to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_vorticity_forces_task(&self, p_i: usize, p_j: usize, ret: &mut Vector) {
+        let r = self.ps.relative_position(p_i, p_j);
+        let volume = self.ps.m[p_j] / self.ps.density[p_j];
+        let gradient = self.cubic_kernel_derivative(r);
+
+        match self.vorticity_model {
+            VorticityModel::Confinement => {
+                *ret += volume * (self.vorticity[p_j].length() - self.vorticity[p_i].length()) * gradient;
+            }
+            VorticityModel::Micropolar => {
+                *ret += volume * (self.ps.omega[p_i] - self.ps.omega[p_j]).cross(gradient);
+            }
+            VorticityModel::None => {}
+        }
+    }
+
+    /// Adds vorticity confinement or micropolar acceleration to each particle
+    pub fn compute_vorticity_forces(&mut self) {
+        if self.vorticity_model == VorticityModel::None {
+            return;
+        }
+
+        self.compute_vorticity();
+        self.angular_acceleration.resize(self.particle_num(), Vector::ZERO);
+
+        for p_i in 0..self.particle_num() {
+            let mut sum = Vector::ZERO;
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_vorticity_forces_task(p_i, p_j, ret), &mut sum);
+
+            match self.vorticity_model {
+                VorticityModel::Confinement => {
+                    let location = sum.normalize_or_zero();
+                    self.ps.acceleration[p_i] += self.vorticity_strength * location.cross(self.vorticity[p_i]);
+                    self.ps.omega[p_i] = 0.5 * self.vorticity[p_i];
+                }
+                VorticityModel::Micropolar => {
+                    self.ps.acceleration[p_i] += self.vorticity_strength * sum;
+                    self.angular_acceleration[p_i] = self.vorticity_strength / self.micropolar_inertia * (
+                        self.vorticity[p_i] - 2.0 * self.ps.omega[p_i]);
+                }
+                VorticityModel::None => {}
+            }
+        }
+    }
+
+    /// Computes heat conducted from particle j to particle i (Cleary & Monaghan 1999) and adds
+    /// it to ret
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `ret` - mutable reference, where the result will be added
+    fn compute_heat_conduction_task(&self, p_i: usize, p_j: usize, ret: &mut Real) {
+        let diffusivity = match self.thermal {
+            Some(thermal) => thermal.diffusivity,
+            None => return,
+        };
+        let r = self.ps.relative_position(p_i, p_j);
+        let volume = self.ps.m[p_j] / self.ps.density[p_j];
+
+        *ret += 2.0 * diffusivity * volume * (self.ps.temperature[p_i] - self.ps.temperature[p_j]) * r.dot(
+            self.cubic_kernel_derivative(r)) / (r.length().powi(2) + 0.01 * self.ps.support_radius.powi(2));
+    }
+
+    /// Updates rate of temperature change and adds buoyancy to acceleration of each particle
+    pub fn compute_heat_transfer(&mut self) {
+        let thermal = match self.thermal {
+            Some(thermal) => thermal,
+            None => return,
+        };
+        self.temperature_rate.resize(self.particle_num(), 0.0);
+
+        for p_i in 0..self.particle_num() {
+            let mut rate = 0.0;
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_heat_conduction_task(p_i, p_j, ret), &mut rate);
+            for region in self.ps.thermal_regions.iter() {
+                if region.contains(self.ps.x[p_i]) {
+                    rate += region.heat_rate(self.ps.temperature[p_i]);
+                }
+            }
+
+            self.temperature_rate[p_i] = rate;
+            self.ps.acceleration[p_i] += thermal.buoyancy(self.ps.temperature[p_i], Self::G);
+        }
     }
 
     /// Updates non-pressure acceleration for each particle
     pub fn compute_non_pressure_forces(&mut self) {
+        self.compute_strain_rates();
+
+        if self.surface_tension_model == SurfaceTensionModel::Akinci {
+            self.compute_normals();
+        }
+
         for p_i in 0..self.ps.x.len() {
             let mut d_v = Self::G;
             self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.compute_non_pressure_forces_task(p_i, p_j, ret), &mut d_v);
             self.ps.acceleration[p_i] = d_v;
         }
+
+        self.compute_vorticity_forces();
+        self.compute_heat_transfer();
+        self.compute_elastic_forces();
     }
 
-    /// For each particle applies its acceleration and velocity
-    pub fn advect(&mut self) {
-        for p_i in 0..self.ps.x.len() {
-            self.ps.v[p_i] += self.delta_time * self.ps.acceleration[p_i];
-            self.ps.x[p_i] += self.delta_time * self.ps.v[p_i];
+    /// Adds elastic acceleration to particles of elastic phases
+    pub fn compute_elastic_forces(&mut self) {
+        if let Some(elasticity) = self.elasticity.as_mut() {
+            let accelerations = elasticity.compute_accelerations(&self.ps, self.delta_time);
+            for (acceleration, elastic) in self.ps.acceleration.iter_mut().zip(accelerations) {
+                *acceleration += elastic;
+            }
+        }
+    }
+
+    /// Computes viscous acceleration of particle i from particle j for velocity field v and adds the
+    /// result to ret, the diagonal of the operator is added to the second item
+    ///
+    /// # Arguments
+    /// * `p_i` - id of particle i
+    /// * `p_j` - id of particle j
+    /// * `v` - velocity field
+    /// * `ret` - mutable reference to (acceleration, diagonal) sums
+    fn viscosity_operator_task(&self, p_i: usize, p_j: usize, v: &[Vector], ret: &mut (Vector, Vector)) {
+        let r = self.ps.relative_position(p_i, p_j);
+        let viscosity = 0.5 * (self.particle_viscosity(p_i) + self.particle_viscosity(p_j));
+
+        let factor = 2.0 * viscosity * (self.ps.m[p_j] / self.ps.density[p_j]) * r.dot(self.cubic_kernel_derivative(r)) / (
+            r.length().powi(2) + 0.01 * self.ps.support_radius.powi(2));
+
+        ret.0 += factor * (v[p_i] - v[p_j]);
+        ret.1 += Vector::splat(factor);
+    }
+
+    /// Computes viscous coupling of particles with boundary particles of rigid bodies. Acceleration
+    /// of particle i is `A_i v_i - c_i`, the boundary velocities are known, so `c_i` moves to
+    /// the right hand side of the implicit system
+    ///
+    /// # Arguments
+    /// * `task` - task executed with body id, boundary particle id, particle id and the
+    ///   contribution to `A_i` and `c_i`
+    fn for_all_boundary_viscosity_terms<F>(&self, mut task: F)
+    where
+        F: FnMut(usize, usize, usize, Matrix, Vector)
+    {
+        self.ps.for_all_boundary_neighbors(|body_id, p_b, p_i, r| {
+            if self.ps.in_outflow_buffer(p_i) {
+                return;
+            }
+
+            let body = &self.ps.rigid_bodies[body_id];
+            let factor = 2.0 * self.particle_viscosity(p_i) * body.boundary_volume[p_b] * r.dot(self.cubic_kernel_derivative(r)) / (
+                r.length().powi(2) + 0.01 * self.ps.support_radius.powi(2));
+            let v_b = body.velocity_at(body.boundary_world[p_b]);
+
+            task(body_id, p_b, p_i, Matrix::IDENTITY * factor, factor * v_b);
+        });
+    }
+
+    /// Applies implicit viscosity operator (I - dt * L) on velocity field
+    ///
+    /// # Arguments
+    /// * `v` - velocity field
+    /// * `boundary` - coupling of each particle with rigid bodies
+    /// * `ret` - result of the operator
+    /// * `diagonal` - diagonal of the operator (used by preconditioner)
+    fn apply_viscosity_operator(&self, v: &[Vector], boundary: &[Matrix], ret: &mut [Vector], diagonal: &mut [Vector]) {
+        for p_i in 0..self.particle_num() {
+            let mut sums = (Vector::ZERO, Vector::ZERO);
+            self.ps.for_all_neighbords(p_i, |p_i, p_j, ret| self.viscosity_operator_task(p_i, p_j, v, ret), &mut sums);
+
+            let boundary_diagonal = vector(boundary[p_i].x_axis.x, boundary[p_i].y_axis.y, boundary[p_i].z_axis.z);
+            ret[p_i] = v[p_i] - self.delta_time * (sums.0 + boundary[p_i] * v[p_i]);
+            diagonal[p_i] = Vector::ONE - self.delta_time * (sums.1 + boundary_diagonal);
+        }
+    }
+
+    /// Solves velocities from implicit viscosity equation (Weiler et al. 2018) using conjugate
+    /// gradients with Jacobi preconditioner, current velocities are the right hand side. Friction
+    /// with rigid bodies is part of the system and its reaction is applied to the bodies
+    pub fn solve_implicit_viscosity(&mut self) {
+        let n = self.particle_num();
+        let mut boundary = vec![Matrix::ZERO; n];
+        let mut b = self.ps.v.clone();
+        self.for_all_boundary_viscosity_terms(|_body_id, _p_b, p_i, a, c| {
+            boundary[p_i] += a;
+            b[p_i] -= self.delta_time * c;
+        });
+
+        let b_norm = b.iter().map(|b_i| b_i.length_squared()).sum::<Real>().sqrt();
+        if b_norm < 1e-12 {
+            return;
+        }
+
+        let mut x = b.clone();
+        let mut a_x = vec![Vector::ZERO; n];
+        let mut diagonal = vec![Vector::ONE; n];
+        self.apply_viscosity_operator(&x, &boundary, &mut a_x, &mut diagonal);
+        let inverse_diagonal = diagonal.iter().map(|d_i| 1.0 / d_i.max(Vector::splat(1e-6))).collect::<Vec<Vector>>();
+
+        let mut residual = b.iter().zip(a_x.iter()).map(|(b_i, a_i)| *b_i - *a_i).collect::<Vec<Vector>>();
+        let mut z = residual.iter().zip(inverse_diagonal.iter()).map(|(r_i, d_i)| *r_i * *d_i).collect::<Vec<Vector>>();
+        let mut p = z.clone();
+        let mut rz = dot(&residual, &z);
+        let mut a_p = vec![Vector::ZERO; n];
+
+        for _iteration in 0..self.viscosity_iterations {
+            if dot(&residual, &residual).sqrt() <= self.viscosity_tolerance * b_norm {
+                break;
+            }
+
+            self.apply_viscosity_operator(&p, &boundary, &mut a_p, &mut diagonal);
+            let p_a_p = dot(&p, &a_p);
+            if p_a_p.abs() < 1e-20 {
+                break;
+            }
+
+            let alpha = rz / p_a_p;
+            for p_i in 0..n {
+                x[p_i] += alpha * p[p_i];
+                residual[p_i] -= alpha * a_p[p_i];
+                z[p_i] = residual[p_i] * inverse_diagonal[p_i];
+            }
+
+            let rz_new = dot(&residual, &z);
+            let beta = rz_new / rz;
+            rz = rz_new;
+            for p_i in 0..n {
+                p[p_i] = z[p_i] + beta * p[p_i];
+            }
+        }
+
+        self.ps.v = x;
+
+        let mut body_forces = Vec::new();
+        self.for_all_boundary_viscosity_terms(|body_id, p_b, p_i, a, c| {
+            let acceleration = a * self.ps.v[p_i] - c;
+            body_forces.push((body_id, -self.ps.m[p_i] * acceleration, self.ps.rigid_bodies[body_id].boundary_world[p_b]));
+        });
+        for (body_id, force, x_b) in body_forces {
+            self.ps.rigid_bodies[body_id].apply_force(force, x_b);
+        }
+    }
+
+    /// Integrates angular velocity (micropolar model) and temperature of particles with rates of
+    /// the last evaluation
+    fn advect_fields(&mut self) {
+        if self.vorticity_model == VorticityModel::Micropolar {
+            for p_i in 0..self.ps.x.len() {
+                self.ps.omega[p_i] += self.delta_time * self.angular_acceleration[p_i];
+            }
+        }
+
+        if self.thermal.is_some() {
+            for p_i in 0..self.ps.x.len() {
+                self.ps.temperature[p_i] += self.delta_time * self.temperature_rate[p_i];
+            }
         }
     }
 
@@ -230,8 +858,16 @@ impl WCSPHSolver {
     /// # Arguments
     /// * `instances` - instances to advect
     pub fn advect_instances(&self, instances: &mut Vec<Instance>) {
-        for (particle_id, instance_id) in self.ps.ids.iter().enumerate() {
-            instances[*instance_id].position = self.ps.x[particle_id].into();
-        }
+        self.ps.advect_instances(instances);
     }
 }
+
+
+/// Dot product of two vector fields
+///
+/// # Arguments
+/// * `a` - first field
+/// * `b` - second field
+fn dot(a: &[Vector], b: &[Vector]) -> Real {
+    a.iter().zip(b.iter()).map(|(a_i, b_i)| a_i.dot(*b_i)).sum()
+}
@@ -0,0 +1,125 @@
+//! Properties of the cubic spline kernel and its gradient for random support radii.
+
+use fluid_renderer::Instance;
+use nikola::{consts, vector, Config, Real, Solver, Vector, WCSPHSolver};
+use proptest::prelude::*;
+
+/// Create solver with single particle, which provides kernel functions
+///
+/// # Arguments
+/// * `particle_radius` - radius of particle, support radius is four times larger
+/// * `dimensions` - number of dimensions of the kernel
+fn solver(particle_radius: Real, dimensions: u32) -> WCSPHSolver {
+    let instances = vec![Instance { position: [0.0; 3], color: [0.0; 3] }];
+    let extent = Vector::splat(8.0 * particle_radius);
+    let mut config = Config::from_instances(-extent, extent, particle_radius, 1000.0, &instances);
+    config.dimensions = dimensions;
+
+    WCSPHSolver::new(0.0, 0.0, 0.0, 0.001, config)
+}
+
+/// Create vector from spherical coordinates, z is zero in 2D
+///
+/// # Arguments
+/// * `length` - length of the vector
+/// * `azimuth` - angle in xy plane
+/// * `inclination` - angle from xy plane, ignored in 2D
+/// * `dimensions` - number of dimensions
+fn direction(length: Real, azimuth: Real, inclination: Real, dimensions: u32) -> Vector {
+    let inclination = if dimensions == 2 { 0.0 } else { inclination };
+    length * vector(azimuth.cos() * inclination.cos(), azimuth.sin() * inclination.cos(), inclination.sin())
+}
+
+/// Integrate kernel over its support by midpoint rule
+///
+/// # Arguments
+/// * `solver` - solver providing the kernel
+/// * `cells` - number of cells along each axis
+fn kernel_integral(solver: &WCSPHSolver, cells: usize) -> Real {
+    let h = solver.support_radius();
+    let cell = 2.0 * h / cells as Real;
+    let center = |i: usize| -h + (i as Real + 0.5) * cell;
+    let z_cells = if solver.dimensions() == 2 { 1 } else { cells };
+
+    let mut integral = 0.0;
+    for x in 0..cells {
+        for y in 0..cells {
+            for z in 0..z_cells {
+                let z = if solver.dimensions() == 2 { 0.0 } else { center(z) };
+                integral += solver.cubic_kernel(vector(center(x), center(y), z).length());
+            }
+        }
+    }
+
+    integral * cell.powi(solver.dimensions() as i32)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn kernel_has_unit_integral(particle_radius in 0.01..2.0 as Real, dimensions in 2..=3u32) {
+        let solver = solver(particle_radius, dimensions);
+        let integral = kernel_integral(&solver, if dimensions == 2 { 200 } else { 60 });
+
+        prop_assert!((integral - 1.0).abs() < 1e-2, "integral of {dimensions}D kernel is {integral}");
+    }
+}
+
+proptest! {
+    #[test]
+    fn kernel_has_compact_support(
+        particle_radius in 0.01..2.0 as Real,
+        dimensions in 2..=3u32,
+        distance in 1.0..3.0 as Real,
+        azimuth in 0.0..consts::TAU,
+        inclination in -consts::FRAC_PI_2..consts::FRAC_PI_2,
+    ) {
+        let solver = solver(particle_radius, dimensions);
+        let r = direction(distance * solver.support_radius(), azimuth, inclination, dimensions);
+
+        prop_assert_eq!(solver.cubic_kernel(r.length()), 0.0);
+        prop_assert_eq!(solver.cubic_kernel_derivative(r), Vector::ZERO);
+    }
+
+    #[test]
+    fn kernel_gradient_matches_numeric_derivative(
+        particle_radius in 0.01..2.0 as Real,
+        dimensions in 2..=3u32,
+        q in 0.05..0.95 as Real,
+        azimuth in 0.0..consts::TAU,
+        inclination in -consts::FRAC_PI_2..consts::FRAC_PI_2,
+    ) {
+        let solver = solver(particle_radius, dimensions);
+        let h = solver.support_radius();
+        let r = direction(q * h, azimuth, inclination, dimensions);
+
+        let epsilon = 1e-3 * h;
+        let derivative = (solver.cubic_kernel(r.length() + epsilon) - solver.cubic_kernel(r.length() - epsilon)) / (2.0 * epsilon);
+        let numeric = derivative * r.normalize();
+        let gradient = solver.cubic_kernel_derivative(r);
+
+        // gradient is compared relative to its maximal magnitude
+        let scale = solver.cubic_kernel(0.0) / h;
+        prop_assert!((gradient - numeric).length() < 1e-2 * scale, "gradient {gradient}, numeric {numeric}");
+    }
+
+    #[test]
+    fn kernel_is_symmetric(
+        particle_radius in 0.01..2.0 as Real,
+        dimensions in 2..=3u32,
+        q in 0.0..1.0 as Real,
+        azimuth in 0.0..consts::TAU,
+        inclination in -consts::FRAC_PI_2..consts::FRAC_PI_2,
+    ) {
+        let solver = solver(particle_radius, dimensions);
+        let r = direction(q * solver.support_radius(), azimuth, inclination, dimensions);
+        let gradient = solver.cubic_kernel_derivative(r);
+
+        prop_assert!(solver.cubic_kernel(r.length()) >= 0.0);
+        prop_assert_eq!(solver.cubic_kernel_derivative(-r), -gradient);
+        // gradient points towards the other particle along the difference vector
+        prop_assert!(gradient.dot(r) <= 0.0);
+        prop_assert!(gradient.cross(r).length() <= 1e-4 * gradient.length() * r.length());
+    }
+}
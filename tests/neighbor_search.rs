@@ -0,0 +1,158 @@
+//! Grid neighbour search compared with brute force search over random particle clouds.
+
+use std::collections::BTreeSet;
+
+use fluid_renderer::Instance;
+use nikola::{to_single, vector, Config, ParticleSystem, Real, Sink, Vector};
+use proptest::prelude::*;
+
+/// Random particle cloud inside the domain `[0, size]`
+#[derive(Debug, Clone)]
+struct Cloud {
+    size: Vector,
+    particle_radius: Real,
+    dimensions: u32,
+    periodic: [bool; 3],
+    /// Positions relative to the domain size
+    positions: Vec<[Real; 3]>,
+}
+
+impl Cloud {
+    /// Create particle system of the cloud
+    fn particle_system(&self) -> ParticleSystem {
+        let instances = self.positions
+            .iter()
+            .map(|position| {
+                let x = Vector::from_array(*position) * self.size;
+                Instance { position: to_single(x).to_array(), color: [0.0; 3] }
+            })
+            .collect::<Vec<Instance>>();
+
+        let mut config = Config::from_instances(Vector::ZERO, self.size, self.particle_radius, 1000.0, &instances);
+        config.dimensions = self.dimensions;
+        config.periodic = self.periodic;
+
+        ParticleSystem::new(config)
+    }
+}
+
+/// Generate clouds of up to 300 particles in domains of random size and periodicity
+fn cloud() -> impl Strategy<Value = Cloud> {
+    (
+        (0.5..4.0 as Real, 0.5..4.0 as Real, 0.5..4.0 as Real),
+        0.02..0.2 as Real,
+        2..=3u32,
+        [any::<bool>(), any::<bool>(), any::<bool>()],
+        prop::collection::vec([0.0..1.0 as Real, 0.0..1.0 as Real, 0.0..1.0 as Real], 1..300),
+    ).prop_map(|(size, particle_radius, dimensions, periodic, positions)| Cloud {
+        size: vector(size.0, size.1, size.2),
+        particle_radius,
+        dimensions,
+        periodic,
+        positions,
+    })
+}
+
+/// Find neighbours of every particle by grid search, particles are identified by original ids
+///
+/// # Arguments
+/// * `ps` - particle system sorted into the grid
+fn grid_neighbors(ps: &ParticleSystem) -> BTreeSet<(usize, usize)> {
+    let mut neighbors = BTreeSet::new();
+    for p_i in 0..ps.particle_num {
+        let mut found = Vec::new();
+        ps.for_all_neighbords(p_i, |p_i, p_j, ret: &mut Vec<(usize, usize)>| ret.push((ps.ids[p_i], ps.ids[p_j])), &mut found);
+
+        let len = found.len();
+        let unique = found.iter().collect::<BTreeSet<_>>().len();
+        assert_eq!(unique, len, "particle {p_i} has duplicate neighbors");
+        neighbors.extend(found);
+    }
+
+    neighbors
+}
+
+/// Find neighbours of every particle by comparing all pairs, particles are identified by original ids
+///
+/// # Arguments
+/// * `ps` - particle system
+fn brute_force_neighbors(ps: &ParticleSystem) -> BTreeSet<(usize, usize)> {
+    let mut neighbors = BTreeSet::new();
+    for p_i in 0..ps.particle_num {
+        for p_j in 0..ps.particle_num {
+            if p_i != p_j && ps.relative_position(p_i, p_j).length() < ps.support_radius {
+                neighbors.insert((ps.ids[p_i], ps.ids[p_j]));
+            }
+        }
+    }
+
+    neighbors
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn grid_search_matches_brute_force(cloud in cloud()) {
+        let mut ps = cloud.particle_system();
+        let expected = brute_force_neighbors(&ps);
+
+        ps.initialize_particle_system();
+        prop_assert_eq!(&grid_neighbors(&ps), &expected);
+        prop_assert_eq!(&brute_force_neighbors(&ps), &expected);
+    }
+
+    #[test]
+    fn sorting_keeps_particles(cloud in cloud(), shift in [-1.0..1.0 as Real, -1.0..1.0 as Real, -1.0..1.0 as Real]) {
+        let mut ps = cloud.particle_system();
+        let original = ps.x.clone();
+
+        ps.initialize_particle_system();
+        for p_i in 0..ps.particle_num {
+            prop_assert_eq!(ps.x[p_i], original[ps.ids[p_i]]);
+        }
+
+        // particles move by a fraction of their radius and are sorted again
+        let shift = 0.5 * ps.particle_radius * Vector::from_array(shift);
+        for x in ps.x.iter_mut() {
+            *x = (*x + shift).clamp(Vector::ZERO, cloud.size * 0.999);
+        }
+        ps.initialize_particle_system();
+        prop_assert_eq!(&grid_neighbors(&ps), &brute_force_neighbors(&ps));
+        prop_assert_eq!(ps.ids.iter().copied().collect::<BTreeSet<usize>>().len(), ps.particle_num);
+    }
+
+    #[test]
+    fn cell_search_of_moved_particles(cloud in cloud(), shift in [-1.0..1.0 as Real, -1.0..1.0 as Real, -1.0..1.0 as Real]) {
+        let mut ps = cloud.particle_system();
+        ps.initialize_particle_system();
+
+        // particles move after the sort, cells are sorted without reordering them
+        let shift = 2.0 * ps.particle_radius * Vector::from_array(shift);
+        for x in ps.x.iter_mut() {
+            *x = (*x + shift).clamp(Vector::ZERO, cloud.size * 0.999);
+        }
+        let cells = ps.grid_cells();
+
+        let mut neighbors = BTreeSet::new();
+        for p_i in 0..ps.particle_num {
+            ps.for_all_particles_near_in(&cells, ps.x[p_i], |p_j, _r| {
+                if p_j != p_i {
+                    neighbors.insert((ps.ids[p_i], ps.ids[p_j]));
+                }
+            });
+        }
+        prop_assert_eq!(&neighbors, &brute_force_neighbors(&ps));
+    }
+
+    #[test]
+    fn grid_search_after_removing_drained_particles(cloud in cloud(), height in 0.0..1.0 as Real) {
+        let mut ps = cloud.particle_system();
+        ps.sinks = vec![Sink::Plane { point: vector(0.0, height * cloud.size.y, 0.0), normal: Vector::Y }];
+
+        ps.initialize_particle_system();
+        ps.remove_drained_particles();
+        prop_assert!(ps.x.iter().all(|x| x.y < height * cloud.size.y));
+        prop_assert_eq!(&grid_neighbors(&ps), &brute_force_neighbors(&ps));
+    }
+}